@@ -0,0 +1,152 @@
+// src/baud.rs
+//! Baud rate detection, for when a device has been reset to factory defaults
+//! and no longer talks at the rate we expect.
+use serialport::SerialPort;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::gps::demux::{GpsFrame, StreamDemuxer};
+use crate::gps::parser::validate_checksum;
+use crate::telemetry::binary::FrameDecoder;
+
+/// Rates to try, most common first. The default for each device is tried
+/// before these.
+pub const STANDARD_RATES: [u32; 9] = [
+    115_200, 9600, 38_400, 57_600, 230_400, 460_800, 921_600, 19_200, 4800,
+];
+
+/// How many valid sentences or lines we want to see before trusting a rate
+const MIN_VALID: usize = 2;
+
+/// Open `port` at `rate` and listen for up to `listen` until `looks_valid`
+/// accepts what was received
+pub fn probe(
+    port: &Path,
+    rate: u32,
+    listen: Duration,
+    looks_valid: impl Fn(&[u8]) -> bool,
+) -> Result<bool, serialport::Error> {
+    let mut device = serialport::new(port.to_string_lossy(), rate)
+        .timeout(Duration::from_millis(50))
+        .open_native()?;
+    let _ = device.clear(serialport::ClearBuffer::Input);
+
+    let mut data = Vec::new();
+    let mut buf = [0u8; 512];
+    let start = Instant::now();
+    while start.elapsed() < listen {
+        match device.read(&mut buf) {
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::TimedOut => {}
+            Err(_) => break,
+        }
+        if looks_valid(&data) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Open `port` at each candidate rate (starting with `default`) and listen
+/// for `listen` until `looks_valid` accepts what was received. Returns the
+/// first rate that works.
+pub fn detect_baud_rate(
+    port: &Path,
+    default: u32,
+    listen: Duration,
+    looks_valid: impl Fn(&[u8]) -> bool,
+) -> Option<u32> {
+    let rates =
+        std::iter::once(default).chain(STANDARD_RATES.into_iter().filter(|&r| r != default));
+
+    for rate in rates {
+        match probe(port, rate, listen, &looks_valid) {
+            Ok(true) => {
+                println!("Detected {} baud on {}", rate, port.to_string_lossy());
+                return Some(rate);
+            }
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!(
+                    "Failed to open \"{}\" at {} baud: {}",
+                    port.to_string_lossy(),
+                    rate,
+                    e
+                );
+                return None;
+            }
+        }
+    }
+    None
+}
+
+/// At least a couple of NMEA sentences with good checksums, or any binary
+/// frame that passed its CRC
+pub fn gps_output_valid(data: &[u8]) -> bool {
+    let frames = StreamDemuxer::new().push(data);
+    let valid_nmea = frames
+        .iter()
+        .filter(|frame| match frame {
+            GpsFrame::Nmea(sentence) => validate_checksum(sentence).is_ok(),
+            _ => false,
+        })
+        .count();
+    let binary = frames
+        .iter()
+        .any(|frame| !matches!(frame, GpsFrame::Nmea(_)));
+    valid_nmea >= MIN_VALID || binary
+}
+
+/// At least a couple of complete lines of comma separated numbers
+pub fn arduino_output_valid(data: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(data);
+    let mut lines: Vec<&str> = text.split('\n').collect();
+    // The last piece is incomplete (or empty)
+    lines.pop();
+    // ...and so may be the first, if we started listening mid-line
+    let valid = lines
+        .iter()
+        .skip(1)
+        .filter(|line| {
+            let fields: Vec<&str> = line.trim().split(',').collect();
+            fields.len() > 1 && fields.iter().all(|f| f.trim().parse::<f64>().is_ok())
+        })
+        .count();
+    valid >= MIN_VALID
+}
+
+/// At least a couple of binary frames that passed their CRC
+pub fn arduino_binary_output_valid(data: &[u8]) -> bool {
+    let frames = FrameDecoder::new().push(data, Instant::now());
+    frames.iter().filter(|(frame, _)| frame.is_ok()).count() >= MIN_VALID
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GGA: &str = "$GNGGA,230206.000,3546.091508,N,07840.396694,W,4,33,0.73,108.098,M,-33.026,M,1.0,0000*67\r\n";
+
+    #[test]
+    fn test_gps_output_valid() {
+        let good = format!("06.0{}{}", GGA, GGA);
+        assert!(gps_output_valid(good.as_bytes()));
+        // What 115200 baud output looks like when read at 9600
+        assert!(!gps_output_valid(&[
+            0x80, 0x00, 0xF8, 0x78, 0x80, 0x80, 0xF8, 0x00
+        ]));
+        let corrupt = GGA.replace("3546.09", "3546.19");
+        assert!(!gps_output_valid(
+            format!("{}{}", corrupt, corrupt).as_bytes()
+        ));
+    }
+
+    #[test]
+    fn test_arduino_output_valid() {
+        let good = b"0.0,12\r\n0.0,0.0,1.5,2.5,1234,100,120,500,300,310\r\n\
+            0.0,0.0,1.5,2.5,2234,100,120,500,300,310\r\n0.0,";
+        assert!(arduino_output_valid(good));
+        assert!(!arduino_output_valid(b"\xf0\x0f\n\xff\xfe\xfd\n\x80\n"));
+    }
+}
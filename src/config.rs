@@ -0,0 +1,43 @@
+// src/config.rs
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::discovery::DevicesConfig;
+use crate::gps::receiver::ReceiverConfig;
+use crate::local_base::LocalBaseConfig;
+use crate::motor::pid::MotorControlConfig;
+use crate::ntrip::NtripConfig;
+use crate::ntrip::caster::CasterConfig;
+
+/// Settings that can be loaded from a TOML config file.
+///
+/// Every section is optional; anything passed on the command line overrides
+/// the value from the file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub ntrip: Option<NtripConfig>,
+    /// Re-serve corrections to other rovers on the local network
+    pub caster: Option<CasterConfig>,
+    /// Read corrections from our own base receiver instead of NTRIP
+    pub local_base: Option<LocalBaseConfig>,
+    /// Settings to apply to the GPS receiver at startup
+    pub receiver: Option<ReceiverConfig>,
+    /// USB IDs of the GPS and Arduino, for finding their ports
+    pub devices: Option<DevicesConfig>,
+    /// RPM setpoints and gains for closed loop motor control
+    pub motor_control: Option<MotorControlConfig>,
+}
+
+/// Load a config file from disk
+pub fn load(path: &Path) -> io::Result<Config> {
+    let contents = fs::read_to_string(path)?;
+    toml::from_str(&contents).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid config file \"{}\": {}", path.display(), e),
+        )
+    })
+}
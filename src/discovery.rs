@@ -0,0 +1,329 @@
+// src/discovery.rs
+//! Find the GPS receiver and Arduino among the connected serial devices, so
+//! it doesn't matter which one enumerated as `/dev/ttyUSB0`.
+use serde::Deserialize;
+use serialport::{SerialPortType, UsbPortInfo};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::baud;
+
+/// How long to listen to an unknown device when probing it
+const PROBE_LISTEN: Duration = Duration::from_millis(1500);
+/// The Arduino resets when the port is opened and needs time to boot
+const ARDUINO_PROBE_LISTEN: Duration = Duration::from_secs(3);
+const GPS_PROBE_BAUD: u32 = 115_200;
+const ARDUINO_PROBE_BAUD: u32 = 9600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceRole {
+    Gps,
+    Arduino,
+}
+
+impl fmt::Display for DeviceRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceRole::Gps => write!(f, "GPS"),
+            DeviceRole::Arduino => write!(f, "Arduino"),
+        }
+    }
+}
+
+/// Identifies a USB device. Fields that aren't set match anything.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct UsbMatch {
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+}
+
+impl UsbMatch {
+    const fn id(vid: u16, pid: Option<u16>) -> Self {
+        Self {
+            vid: Some(vid),
+            pid,
+            serial_number: None,
+        }
+    }
+
+    fn matches(&self, info: &UsbPortInfo) -> bool {
+        self.vid.is_none_or(|vid| vid == info.vid)
+            && self.pid.is_none_or(|pid| pid == info.pid)
+            && self
+                .serial_number
+                .as_ref()
+                .is_none_or(|serial| info.serial_number.as_ref() == Some(serial))
+    }
+}
+
+/// USB adapters the receivers we use come with: u-blox, Silicon Labs CP210x
+/// and FTDI
+fn default_gps_matches() -> Vec<UsbMatch> {
+    vec![
+        UsbMatch::id(0x1546, None),
+        UsbMatch::id(0x10C4, Some(0xEA60)),
+        UsbMatch::id(0x0403, Some(0x6001)),
+    ]
+}
+
+/// Genuine Arduinos and the CH340 clones
+fn default_arduino_matches() -> Vec<UsbMatch> {
+    vec![
+        UsbMatch::id(0x2341, None),
+        UsbMatch::id(0x2A03, None),
+        UsbMatch::id(0x1A86, Some(0x7523)),
+    ]
+}
+
+/// The `[devices]` section of the config file. Setting a device replaces the
+/// built-in list of USB IDs for it.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DevicesConfig {
+    pub gps: Option<UsbMatch>,
+    pub arduino: Option<UsbMatch>,
+}
+
+impl DevicesConfig {
+    /// Which role a USB device has, judging by its IDs alone
+    pub fn match_role(&self, info: &UsbPortInfo) -> Option<DeviceRole> {
+        let gps = self
+            .gps
+            .clone()
+            .map_or_else(default_gps_matches, |m| vec![m]);
+        let arduino = self
+            .arduino
+            .clone()
+            .map_or_else(default_arduino_matches, |m| vec![m]);
+        if gps.iter().any(|m| m.matches(info)) {
+            Some(DeviceRole::Gps)
+        } else if arduino.iter().any(|m| m.matches(info)) {
+            Some(DeviceRole::Arduino)
+        } else {
+            None
+        }
+    }
+}
+
+/// A serial device and the role we'd give it
+#[derive(Debug, Clone)]
+pub struct DiscoveredPort {
+    pub path: PathBuf,
+    pub usb: Option<UsbPortInfo>,
+    pub role: Option<DeviceRole>,
+    /// How the role was decided, e.g. "USB ID" or "probe"
+    pub reason: &'static str,
+    /// The rate the device was heard at, if it was probed
+    pub baud_rate: Option<u32>,
+}
+
+impl DiscoveredPort {
+    /// One line description of the USB device, e.g. "1546:01a9 u-blox GNSS"
+    pub fn description(&self) -> String {
+        match &self.usb {
+            Some(usb) => format!(
+                "{:04x}:{:04x} {} {} {}",
+                usb.vid,
+                usb.pid,
+                usb.manufacturer.as_deref().unwrap_or_default(),
+                usb.product.as_deref().unwrap_or_default(),
+                usb.serial_number.as_deref().unwrap_or_default()
+            )
+            .trim_end()
+            .to_string(),
+            None => "not USB".to_string(),
+        }
+    }
+}
+
+/// List the serial devices and assign roles, first by USB ID and then, if
+/// `probe` is set, by listening to the unclaimed USB devices for NMEA or
+/// Arduino output. Each role goes to at most one device. Devices in
+/// `reserved`, e.g. the local base's port, are listed but never given a
+/// role or opened.
+pub fn discover(
+    config: &DevicesConfig,
+    reserved: &[PathBuf],
+    probe: bool,
+) -> serialport::Result<Vec<DiscoveredPort>> {
+    let mut ports: Vec<DiscoveredPort> = serialport::available_ports()?
+        .into_iter()
+        .map(|port| {
+            let usb = match port.port_type {
+                SerialPortType::UsbPort(info) => Some(info),
+                _ => None,
+            };
+            DiscoveredPort {
+                path: PathBuf::from(port.port_name),
+                usb,
+                role: None,
+                reason: "",
+                baud_rate: None,
+            }
+        })
+        .collect();
+    assign_roles(&mut ports, config, reserved, probe);
+    Ok(ports)
+}
+
+fn is_reserved(path: &Path, reserved: &[PathBuf]) -> bool {
+    reserved.iter().any(|reserved| reserved == path)
+}
+
+fn assign_roles(
+    ports: &mut [DiscoveredPort],
+    config: &DevicesConfig,
+    reserved: &[PathBuf],
+    probe: bool,
+) {
+    for port in ports.iter_mut() {
+        if is_reserved(&port.path, reserved) {
+            port.reason = "reserved";
+        }
+    }
+
+    for role in [DeviceRole::Gps, DeviceRole::Arduino] {
+        let by_id = ports.iter_mut().find(|port| {
+            port.role.is_none()
+                && !is_reserved(&port.path, reserved)
+                && port
+                    .usb
+                    .as_ref()
+                    .is_some_and(|usb| config.match_role(usb) == Some(role))
+        });
+        if let Some(port) = by_id {
+            port.role = Some(role);
+            port.reason = "USB ID";
+        }
+    }
+
+    if probe {
+        for role in [DeviceRole::Gps, DeviceRole::Arduino] {
+            if ports.iter().any(|port| port.role == Some(role)) {
+                continue;
+            }
+            for port in ports.iter_mut() {
+                if port.role.is_some() || port.usb.is_none() || is_reserved(&port.path, reserved) {
+                    continue;
+                }
+                if let Some(rate) = probe_role(port, role) {
+                    port.role = Some(role);
+                    port.reason = "probe";
+                    port.baud_rate = Some(rate);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Listen to a device at the default rate for `role`, returning the rate if
+/// it sounds like one
+fn probe_role(port: &DiscoveredPort, role: DeviceRole) -> Option<u32> {
+    let (rate, result) = match role {
+        DeviceRole::Gps => (
+            GPS_PROBE_BAUD,
+            baud::probe(
+                &port.path,
+                GPS_PROBE_BAUD,
+                PROBE_LISTEN,
+                baud::gps_output_valid,
+            ),
+        ),
+        DeviceRole::Arduino => (
+            ARDUINO_PROBE_BAUD,
+            baud::probe(
+                &port.path,
+                ARDUINO_PROBE_BAUD,
+                ARDUINO_PROBE_LISTEN,
+                |data: &[u8]| {
+                    baud::arduino_output_valid(data) || baud::arduino_binary_output_valid(data)
+                },
+            ),
+        ),
+    };
+    result.unwrap_or(false).then_some(rate)
+}
+
+/// The device discovered for `role`, if any
+pub fn find(ports: &[DiscoveredPort], role: DeviceRole) -> Option<&DiscoveredPort> {
+    ports.iter().find(|port| port.role == Some(role))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usb(vid: u16, pid: u16, serial: Option<&str>) -> UsbPortInfo {
+        UsbPortInfo {
+            vid,
+            pid,
+            serial_number: serial.map(String::from),
+            manufacturer: None,
+            product: None,
+        }
+    }
+
+    fn port(path: &str, usb: UsbPortInfo) -> DiscoveredPort {
+        DiscoveredPort {
+            path: PathBuf::from(path),
+            usb: Some(usb),
+            role: None,
+            reason: "",
+            baud_rate: None,
+        }
+    }
+
+    #[test]
+    fn test_reserved_ports() {
+        // The local base uses the same kind of adapter as the rover's GPS
+        let mut ports = vec![
+            port("/dev/ttyUSB0", usb(0x10C4, 0xEA60, None)),
+            port("/dev/ttyUSB1", usb(0x10C4, 0xEA60, None)),
+            port("/dev/ttyACM0", usb(0x2341, 0x0043, None)),
+        ];
+        let reserved = [PathBuf::from("/dev/ttyUSB0")];
+        assign_roles(&mut ports, &DevicesConfig::default(), &reserved, false);
+        assert_eq!(ports[0].role, None);
+        assert_eq!(ports[0].reason, "reserved");
+        assert_eq!(find(&ports, DeviceRole::Gps).unwrap().path, ports[1].path);
+        assert_eq!(
+            find(&ports, DeviceRole::Arduino).unwrap().path,
+            ports[2].path
+        );
+    }
+
+    #[test]
+    fn test_match_role() {
+        let config = DevicesConfig::default();
+        assert_eq!(
+            config.match_role(&usb(0x1546, 0x01A9, None)),
+            Some(DeviceRole::Gps)
+        );
+        assert_eq!(
+            config.match_role(&usb(0x2341, 0x0043, None)),
+            Some(DeviceRole::Arduino)
+        );
+        assert_eq!(config.match_role(&usb(0x1234, 0x5678, None)), None);
+
+        // Two identical adapters told apart by serial number
+        let config = DevicesConfig {
+            gps: Some(UsbMatch {
+                serial_number: Some("GPS01".to_string()),
+                ..UsbMatch::default()
+            }),
+            arduino: Some(UsbMatch::id(0x1A86, Some(0x7523))),
+        };
+        assert_eq!(
+            config.match_role(&usb(0x1A86, 0x7523, Some("GPS01"))),
+            Some(DeviceRole::Gps)
+        );
+        assert_eq!(
+            config.match_role(&usb(0x1A86, 0x7523, Some("ARD01"))),
+            Some(DeviceRole::Arduino)
+        );
+    }
+}
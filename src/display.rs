@@ -4,14 +4,39 @@ use crossterm::{
     style::{Color, Print, ResetColor, SetForegroundColor},
     terminal::{Clear, ClearType},
 };
+use std::collections::BTreeMap;
 use std::io::Write;
+use std::time::Instant;
 
-use nmea::{Nmea, sentences::FixType};
+use nmea::Nmea;
 
-use crate::usb_serial::SensorData;
+use crate::gps::fix::{FixState, FixTracker};
+use crate::gps::satellites::SatelliteTracker;
+use crate::motor::CommandOutcome;
+use crate::rtcm::messages::BaseStation;
+use crate::rtcm::monitor::CorrectionMonitor;
+use crate::serial_link::LinkState;
+use crate::telemetry::TelemetryRecord;
 
+/// The parts of the screen, top to bottom
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Panel {
+    Links,
+    Gps,
+    Fix,
+    Satellites,
+    Corrections,
+    BaseStation,
+    Arduino,
+    Motors,
+}
+
+/// The `update_*` methods set what each panel shows, and `draw` redraws all
+/// of them at once so they don't overwrite each other
+#[derive(Default)]
 pub struct Display {
     prev_lines: u16,
+    panels: BTreeMap<Panel, Vec<DisplayItem>>,
 }
 
 enum DisplayItem {
@@ -22,25 +47,11 @@ enum DisplayItem {
 
 impl Display {
     pub fn new() -> Self {
-        Self { prev_lines: 0 }
+        Self::default()
     }
 
-    // Private helper to render a list of DisplayItems
-    fn render<W: Write>(&mut self, stdout: &mut W, items: &[DisplayItem]) -> std::io::Result<()> {
-        let max_len = items
-            .iter()
-            .filter_map(|item| {
-                if let DisplayItem::Data(label, _, _) = item {
-                    Some(label.len())
-                } else {
-                    None
-                }
-            })
-            .max()
-            .unwrap_or(0);
-
-        let line_count = items.len() as u16;
-
+    /// Replace what was shown before with every panel's latest items
+    pub fn draw<W: Write>(&mut self, stdout: &mut W) -> std::io::Result<()> {
         if self.prev_lines > 0 {
             queue!(
                 stdout,
@@ -48,49 +59,16 @@ impl Display {
                 Clear(ClearType::FromCursorDown)
             )?;
         }
-
-        for item in items {
-            match item {
-                DisplayItem::Header(s, c) | DisplayItem::Divider(s, c) => {
-                    queue!(
-                        stdout,
-                        SetForegroundColor(*c),
-                        Print(s),
-                        ResetColor,
-                        Print("\n")
-                    )?;
-                }
-                DisplayItem::Data(label, value, opt_unit) => {
-                    let padded = format!("{:<width$}: ", label, width = max_len);
-                    queue!(stdout, Print(padded))?;
-                    queue!(
-                        stdout,
-                        SetForegroundColor(Color::Green),
-                        Print(value),
-                        ResetColor
-                    )?;
-                    if let Some(unit) = opt_unit {
-                        queue!(
-                            stdout,
-                            Print(" "),
-                            SetForegroundColor(Color::Red),
-                            Print(unit),
-                            ResetColor
-                        )?;
-                    }
-                    queue!(stdout, Print("\n"))?;
-                }
-            }
+        for items in self.panels.values() {
+            render(stdout, items)?;
         }
-
         stdout.flush()?;
 
-        self.prev_lines = line_count;
+        self.prev_lines = self.panels.values().map(|items| items.len() as u16).sum();
 
         Ok(())
     }
-
-    pub fn update_gps<W: Write>(&mut self, stdout: &mut W, parser: &Nmea) -> std::io::Result<()> {
+    pub fn update_gps(&mut self, parser: &Nmea) {
         let sats = parser.satellites();
         let mut avg_snr = 0u32;
         let mut count = 0u32;
@@ -100,7 +78,7 @@ impl Display {
                 count += 1;
             }
         }
-        let avg_snr_value = if count > 0 { avg_snr / count } else { 0 };
+        let avg_snr_value = avg_snr.checked_div(count).unwrap_or(0);
 
         let items = vec![
             DisplayItem::Header("=== GPS DATA ===".to_string(), Color::Yellow),
@@ -126,7 +104,7 @@ impl Display {
             ),
             DisplayItem::Data(
                 "Fix Type".to_string(),
-                format!("{:?}", parser.fix_type.unwrap_or_else(|| FixType::Simulation)),
+                format!("{}", FixState::from_fix_type(parser.fix_type)),
                 None,
             ),
             DisplayItem::Data(
@@ -150,78 +128,271 @@ impl Display {
             ),
         ];
 
-        self.render(stdout, &items)
+        self.panels.insert(Panel::Gps, items);
     }
 
-    pub fn update_arduino<W: Write>(
-        &mut self,
-        stdout: &mut W,
-        arduino_data: &SensorData,
-    ) -> std::io::Result<()> {
-        let items = vec![
-            DisplayItem::Header("=== Arduino Sensor Data ===".to_string(), Color::Yellow),
+    /// Every field the Arduino sends, labelled with the names from its header
+    pub fn update_arduino(&mut self, record: &TelemetryRecord) {
+        let mut items = vec![DisplayItem::Header(
+            "=== Arduino Sensor Data ===".to_string(),
+            Color::Yellow,
+        )];
+        for (field, value) in record.iter() {
+            items.push(DisplayItem::Data(
+                field.name.clone(),
+                format!("{:?}", value),
+                field.unit.clone(),
+            ));
+        }
+
+        self.panels.insert(Panel::Arduino, items);
+    }
+
+    /// The last motor command's outcome, and the last one that failed
+    pub fn update_motors(&mut self, last: &CommandOutcome, last_failed: Option<&CommandOutcome>) {
+        let mut items = vec![
+            DisplayItem::Header("=== Motors ===".to_string(), Color::Yellow),
+            DisplayItem::Data("Last Command".to_string(), last.message(), None),
+        ];
+        if let Some(failed) = last_failed {
+            items.push(DisplayItem::Header(
+                format!("Last failed: {}", failed.message()),
+                Color::Red,
+            ));
+        }
+
+        self.panels.insert(Panel::Motors, items);
+    }
+
+    pub fn update_base_station(&mut self, base: &BaseStation, baseline_m: Option<f64>) {
+        let mut items = vec![DisplayItem::Header(
+            "=== Base Station ===".to_string(),
+            Color::Yellow,
+        )];
+
+        if let Some(position) = &base.position {
+            items.extend([
+                DisplayItem::Data("Station ID".to_string(), format!("{}", position.station_id), None),
+                DisplayItem::Data(
+                    "ECEF X".to_string(),
+                    format!("{:.4}", position.ecef_x),
+                    Some("m".to_string()),
+                ),
+                DisplayItem::Data(
+                    "ECEF Y".to_string(),
+                    format!("{:.4}", position.ecef_y),
+                    Some("m".to_string()),
+                ),
+                DisplayItem::Data(
+                    "ECEF Z".to_string(),
+                    format!("{:.4}", position.ecef_z),
+                    Some("m".to_string()),
+                ),
+                DisplayItem::Data(
+                    "Antenna Height".to_string(),
+                    position.antenna_height.map_or("-".to_string(), |h| format!("{:.4}", h)),
+                    Some("m".to_string()),
+                ),
+            ]);
+        }
+        items.push(DisplayItem::Data(
+            "Baseline".to_string(),
+            baseline_m.map_or("-".to_string(), |b| format!("{:.1}", b)),
+            Some("m".to_string()),
+        ));
+        if let Some(descriptor) = &base.descriptor {
+            items.extend([
+                DisplayItem::Data("Receiver".to_string(), descriptor.receiver.clone(), None),
+                DisplayItem::Data("Firmware".to_string(), descriptor.firmware.clone(), None),
+                DisplayItem::Data("Antenna".to_string(), descriptor.antenna.clone(), None),
+            ]);
+        }
+        items.push(DisplayItem::Data(
+            "Satellites".to_string(),
+            base.satellite_summary(),
+            None,
+        ));
+
+        self.panels.insert(Panel::BaseStation, items);
+    }
+
+    pub fn update_corrections(&mut self, monitor: &CorrectionMonitor) {
+        let now = Instant::now();
+        let mut items = vec![DisplayItem::Header(
+            "=== Corrections ===".to_string(),
+            Color::Yellow,
+        )];
+        if monitor.is_stale() {
+            items.push(DisplayItem::Header(
+                "!!! CORRECTIONS STALE - POSITION NOT RTK !!!".to_string(),
+                Color::Red,
+            ));
+        }
+        items.extend([
             DisplayItem::Data(
-                "Sonar".to_string(),
-                format!("{:?}", arduino_data.sonar_mm),
-                Some("mm".to_string()),
+                "Correction Age".to_string(),
+                format!("{:.1}", monitor.correction_age(now).as_secs_f64()),
+                Some("s".to_string()),
             ),
             DisplayItem::Data(
-                "ToF1".to_string(),
-                format!("{:?}", arduino_data.tof1_mm),
-                Some("mm".to_string()),
+                "Differential Age".to_string(),
+                monitor.differential_age().map_or("-".to_string(), |a| format!("{:.1}", a)),
+                Some("s".to_string()),
             ),
             DisplayItem::Data(
-                "ToF2".to_string(),
-                format!("{:?}", arduino_data.tof2_mm),
-                Some("mm".to_string()),
+                "Data Rate".to_string(),
+                format!("{:.0}", monitor.bytes_per_sec),
+                Some("B/s".to_string()),
             ),
             DisplayItem::Data(
-                "Current Motor 1".to_string(),
-                format!("{:?}", arduino_data.current_motor_1_ma),
-                Some("A".to_string()),
+                "Message Rate".to_string(),
+                format!("{:.1}", monitor.messages_per_sec),
+                Some("msg/s".to_string()),
             ),
+            DisplayItem::Data("Messages".to_string(), format!("{}", monitor.total_messages), None),
+        ]);
+
+        self.panels.insert(Panel::Corrections, items);
+    }
+
+    pub fn update_fix(&mut self, tracker: &FixTracker) {
+        let now = Instant::now();
+        let seconds = |duration: Option<std::time::Duration>| {
+            duration.map_or("-".to_string(), |d| format!("{:.1}", d.as_secs_f64()))
+        };
+        let mut items = vec![DisplayItem::Header(
+            "=== Fix ===".to_string(),
+            Color::Yellow,
+        )];
+        if let Some(alert) = tracker.alert() {
+            items.push(DisplayItem::Header(format!("!!! {} !!!", alert), Color::Red));
+        }
+        items.extend([
+            DisplayItem::Data("State".to_string(), format!("{}", tracker.state()), None),
             DisplayItem::Data(
-                "Current Motor 2".to_string(),
-                format!("{:?}", arduino_data.current_motor_2_ma),
-                Some("A".to_string()),
+                "Time in State".to_string(),
+                seconds(Some(tracker.time_in_state(now))),
+                Some("s".to_string()),
             ),
-            // DisplayItem::Data(
-            //     "Roll".to_string(),
-            //     format!("{:?}", arduino_data.roll),
-            //     Some("°".to_string()),
-            // ),
-            // DisplayItem::Data(
-            //     "Pitch".to_string(),
-            //     format!("{:?}", arduino_data.pitch),
-            //     Some("°".to_string()),
-            // ),
-            // DisplayItem::Data(
-            //     "Yaw".to_string(),
-            //     format!("{:?}", arduino_data.yaw),
-            //     Some("°".to_string()),
-            // ),
             DisplayItem::Data(
-                "Motor 1 RPM".to_string(),
-                format!("{:?}", arduino_data.motor_1_rpm),
-                None,
+                "Time to First Fix".to_string(),
+                seconds(tracker.time_to_first_fix),
+                Some("s".to_string()),
             ),
             DisplayItem::Data(
-                "Motor 2 RPM".to_string(),
-                format!("{:?}", arduino_data.motor_2_rpm),
-                None,
+                "Time to RTK Fixed".to_string(),
+                seconds(tracker.time_to_rtk_fixed),
+                Some("s".to_string()),
             ),
+            DisplayItem::Data("Fix Losses".to_string(), format!("{}", tracker.fix_losses), None),
             DisplayItem::Data(
-                "Motor 1 Total Rotations".to_string(),
-                format!("{:?}", arduino_data.motor_1_tot_rotations),
+                "RTK Fixed Losses".to_string(),
+                format!("{}", tracker.rtk_fixed_losses),
                 None,
             ),
-            DisplayItem::Data(
-                "Motor 2 Total Rotations".to_string(),
-                format!("{:?}", arduino_data.motor_2_tot_rotations),
+        ]);
+
+        self.panels.insert(Panel::Fix, items);
+    }
+
+    /// One line per constellation, e.g. "GPS: 8/11 used, 38 dB-Hz, 2 dropouts"
+    pub fn update_satellites(&mut self, tracker: &SatelliteTracker) {
+        let mut items = vec![DisplayItem::Header(
+            "=== Satellites ===".to_string(),
+            Color::Yellow,
+        )];
+        for (constellation, status) in tracker.iter() {
+            items.push(DisplayItem::Data(
+                constellation.to_string(),
+                format!(
+                    "{}/{} used, {} dB-Hz, {} dropouts",
+                    status.used(),
+                    status.in_view(),
+                    status.mean_snr().map_or("-".to_string(), |snr| format!("{:.0}", snr)),
+                    status.dropouts
+                ),
                 None,
-            ),
-        ];
+            ));
+        }
 
-        self.render(stdout, &items)
+        self.panels.insert(Panel::Satellites, items);
     }
+
+    /// Show whether each serial device is connected and how many times it
+    /// was reopened, e.g. ("GPS", state, reconnects)
+    pub fn update_links(&mut self, links: &[(&str, LinkState, u32)]) {
+        let mut items = vec![DisplayItem::Header(
+            "=== Serial Links ===".to_string(),
+            Color::Yellow,
+        )];
+        for (name, state, reconnects) in links {
+            items.push(match state {
+                LinkState::Connected if *reconnects > 0 => DisplayItem::Data(
+                    name.to_string(),
+                    format!("Connected ({} reconnects)", reconnects),
+                    None,
+                ),
+                LinkState::Connected => {
+                    DisplayItem::Data(name.to_string(), "Connected".to_string(), None)
+                }
+                LinkState::Disconnected => DisplayItem::Header(
+                    format!("{}: DISCONNECTED, retrying", name),
+                    Color::Red,
+                ),
+            });
+        }
+
+        self.panels.insert(Panel::Links, items);
+    }
+}
+
+/// Print one panel, with the values lined up
+fn render<W: Write>(stdout: &mut W, items: &[DisplayItem]) -> std::io::Result<()> {
+    let max_len = items
+        .iter()
+        .filter_map(|item| {
+            if let DisplayItem::Data(label, _, _) = item {
+                Some(label.len())
+            } else {
+                None
+            }
+        })
+        .max()
+        .unwrap_or(0);
+
+    for item in items {
+        match item {
+            DisplayItem::Header(s, c) | DisplayItem::Divider(s, c) => {
+                queue!(
+                    stdout,
+                    SetForegroundColor(*c),
+                    Print(s),
+                    ResetColor,
+                    Print("\n")
+                )?;
+            }
+            DisplayItem::Data(label, value, opt_unit) => {
+                let padded = format!("{:<width$}: ", label, width = max_len);
+                queue!(stdout, Print(padded))?;
+                queue!(
+                    stdout,
+                    SetForegroundColor(Color::Green),
+                    Print(value),
+                    ResetColor
+                )?;
+                if let Some(unit) = opt_unit {
+                    queue!(
+                        stdout,
+                        Print(" "),
+                        SetForegroundColor(Color::Red),
+                        Print(unit),
+                        ResetColor
+                    )?;
+                }
+                queue!(stdout, Print("\n"))?;
+            }
+        }
+    }
+
+    Ok(())
 }
@@ -1 +1,6 @@
+pub mod demux;
+pub mod fix;
 pub mod parser;
+pub mod receiver;
+pub mod satellites;
+pub mod ubx;
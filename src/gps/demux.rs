@@ -0,0 +1,231 @@
+// src/gps/demux.rs
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use crate::gps::ubx::{self, UbxFrame, UbxParse};
+use crate::rtcm::framer::{self, RtcmFrame, RtcmParse};
+use crate::timing::{ArrivalTime, Timed};
+
+/// NMEA 0183 limits sentences to 82 characters, but some proprietary `$P...`
+/// sentences run longer
+const MAX_NMEA_LEN: usize = 256;
+
+/// A complete frame read from the receiver port
+#[derive(Debug, Clone, PartialEq)]
+pub enum GpsFrame {
+    /// NMEA sentence including its `\r\n`
+    Nmea(String),
+    Rtcm(RtcmFrame),
+    Ubx(UbxFrame),
+}
+
+enum NmeaParse {
+    Complete(usize),
+    Incomplete,
+    Invalid,
+}
+
+/// Look for an NMEA sentence at the start of `buf`. Sentences are printable
+/// ASCII, so a `$` inside binary data is rejected as soon as a non-text byte
+/// turns up.
+fn parse_nmea(buf: &[u8]) -> NmeaParse {
+    if buf.first() != Some(&b'$') {
+        return NmeaParse::Invalid;
+    }
+    for (i, &byte) in buf.iter().enumerate().skip(1).take(MAX_NMEA_LEN) {
+        match byte {
+            b'\r' => {
+                return match buf.get(i + 1) {
+                    Some(b'\n') => NmeaParse::Complete(i + 2),
+                    Some(_) => NmeaParse::Invalid,
+                    None => NmeaParse::Incomplete,
+                };
+            }
+            b' '..=b'~' => {}
+            _ => return NmeaParse::Invalid,
+        }
+    }
+    if buf.len() > MAX_NMEA_LEN {
+        NmeaParse::Invalid
+    } else {
+        NmeaParse::Incomplete
+    }
+}
+
+/// Splits the receiver's output, which mixes NMEA text with RTCM3 and UBX
+/// binary frames, into complete frames.
+#[derive(Debug, Default)]
+pub struct StreamDemuxer {
+    buffer: Vec<u8>,
+    /// How many bytes of the buffer came from each read, and when
+    chunks: VecDeque<(usize, Instant)>,
+    /// Bytes that didn't belong to any recognized frame
+    pub unknown_bytes: u64,
+}
+
+impl StreamDemuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add newly received bytes and return every frame they complete
+    pub fn push(&mut self, data: &[u8]) -> Vec<GpsFrame> {
+        self.push_at(data, Instant::now())
+            .into_iter()
+            .map(|frame| frame.value)
+            .collect()
+    }
+
+    /// Like `push`, for bytes read at `now`. Each frame comes with when its
+    /// first and last bytes were read.
+    pub fn push_at(&mut self, data: &[u8], now: Instant) -> Vec<Timed<GpsFrame>> {
+        self.buffer.extend_from_slice(data);
+        if !data.is_empty() {
+            self.chunks.push_back((data.len(), now));
+        }
+        let mut frames = Vec::new();
+
+        while let Some(&first) = self.buffer.first() {
+            let (frame, len) = match first {
+                b'$' => match parse_nmea(&self.buffer) {
+                    NmeaParse::Complete(len) => {
+                        let sentence = String::from_utf8_lossy(&self.buffer[..len]).into_owned();
+                        (Some(GpsFrame::Nmea(sentence)), len)
+                    }
+                    NmeaParse::Incomplete => break,
+                    NmeaParse::Invalid => (None, 1),
+                },
+                framer::PREAMBLE => match framer::parse_frame(&self.buffer) {
+                    RtcmParse::Complete(frame) => {
+                        let len = frame.as_bytes().len();
+                        (Some(GpsFrame::Rtcm(frame)), len)
+                    }
+                    RtcmParse::Incomplete => break,
+                    RtcmParse::Invalid => (None, 1),
+                },
+                byte if byte == ubx::SYNC[0] => match ubx::parse_frame(&self.buffer) {
+                    UbxParse::Complete(frame) => {
+                        let len = frame.as_bytes().len();
+                        (Some(GpsFrame::Ubx(frame)), len)
+                    }
+                    UbxParse::Incomplete => break,
+                    UbxParse::Invalid => (None, 1),
+                },
+                _ => (None, 1),
+            };
+
+            self.buffer.drain(..len);
+            let arrival = self.consume_chunks(len, now);
+            match frame {
+                Some(value) => frames.push(Timed { value, arrival }),
+                None => self.unknown_bytes += len as u64,
+            }
+        }
+
+        frames
+    }
+
+    /// Drop `len` bytes from the front of the chunk list, returning when the
+    /// first and last of them were read
+    fn consume_chunks(&mut self, mut len: usize, now: Instant) -> ArrivalTime {
+        let mut arrival = ArrivalTime::at(self.chunks.front().map_or(now, |chunk| chunk.1));
+        while len > 0 {
+            let Some((remaining, instant)) = self.chunks.front_mut() else {
+                break;
+            };
+            arrival.last_byte = *instant;
+            if *remaining > len {
+                *remaining -= len;
+                break;
+            }
+            len -= *remaining;
+            self.chunks.pop_front();
+        }
+        arrival
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mixed_stream() {
+        let gga = b"$GNGGA,123519.00,3546.8,N,07838.4,W,4,12,0.6,95.1,M,-33.0,M,1.2,0001*5A\r\n";
+        // Binary frames that contain `$` and `\r\n` must survive intact
+        let rtcm = framer::build_frame(&[0x3E, 0xD0, b'$', b'\r', b'\n']);
+        let ubx = ubx::build_frame(0x05, 0x01, b"$\r");
+
+        let mut stream = vec![0x00, 0xFF];
+        stream.extend_from_slice(&rtcm);
+        stream.extend_from_slice(gga);
+        stream.extend_from_slice(&ubx);
+        stream.extend_from_slice(b"$GNG\x00garbage");
+        stream.extend_from_slice(gga);
+
+        let mut demuxer = StreamDemuxer::new();
+        let mut frames = Vec::new();
+        for chunk in stream.chunks(7) {
+            frames.extend(demuxer.push(chunk));
+        }
+
+        assert_eq!(frames.len(), 4);
+        assert!(matches!(&frames[0], GpsFrame::Rtcm(frame) if frame.as_bytes() == rtcm));
+        assert_eq!(
+            frames[1],
+            GpsFrame::Nmea(String::from_utf8_lossy(gga).into_owned())
+        );
+        assert!(matches!(&frames[2], GpsFrame::Ubx(frame) if frame.as_bytes() == ubx));
+        assert!(matches!(&frames[3], GpsFrame::Nmea(_)));
+        assert_eq!(demuxer.unknown_bytes, 2 + 12);
+    }
+
+    #[test]
+    fn test_arrival_times() {
+        let gga = b"$GNGGA,123519.00,3546.8,N,07838.4,W,4,12,0.6,95.1,M,-33.0,M,1.2,0001*5A\r\n";
+        let start = Instant::now();
+        let at = |ms| start + std::time::Duration::from_millis(ms);
+
+        let mut demuxer = StreamDemuxer::new();
+        assert!(demuxer.push_at(&gga[..10], at(0)).is_empty());
+        assert!(demuxer.push_at(&gga[10..40], at(5)).is_empty());
+        let mut data = gga[40..].to_vec();
+        data.extend_from_slice(&gga[..20]);
+        let frames = demuxer.push_at(&data, at(10));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].arrival.first_byte, at(0));
+        assert_eq!(frames[0].arrival.last_byte, at(10));
+
+        // The second sentence started in the same read the first one ended
+        let frames = demuxer.push_at(&gga[20..], at(15));
+        assert_eq!(frames[0].arrival.first_byte, at(10));
+        assert_eq!(frames[0].arrival.last_byte, at(15));
+    }
+
+    #[test]
+    fn test_field_capture() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/field_tests/volleyball/3ft.log"
+        );
+        let data = std::fs::read(path).unwrap();
+
+        let mut demuxer = StreamDemuxer::new();
+        let frames: Vec<GpsFrame> = data
+            .chunks(1024)
+            .flat_map(|chunk| demuxer.push(chunk))
+            .collect();
+        let nmea = frames
+            .iter()
+            .filter(|f| matches!(f, GpsFrame::Nmea(_)))
+            .count();
+        let rtcm = frames
+            .iter()
+            .filter(|f| matches!(f, GpsFrame::Rtcm(_)))
+            .count();
+
+        assert!(nmea > 0 && rtcm > 0);
+        // Nearly everything in the capture should be accounted for
+        assert!(demuxer.unknown_bytes * 100 < data.len() as u64);
+    }
+}
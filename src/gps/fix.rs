@@ -0,0 +1,230 @@
+// src/gps/fix.rs
+//! Follows the receiver's fix quality over time, so we know how long it took
+//! to get RTK fixed and how often we dropped out of it.
+use nmea::sentences::FixType;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Fix quality, worst to best
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FixState {
+    NoFix,
+    Gps,
+    Dgps,
+    RtkFloat,
+    RtkFixed,
+}
+
+impl FixState {
+    /// Dead reckoning, manual and simulated positions aren't a real fix
+    pub fn from_fix_type(fix_type: Option<FixType>) -> Self {
+        match fix_type {
+            Some(FixType::Gps) => FixState::Gps,
+            Some(FixType::DGps) | Some(FixType::Pps) => FixState::Dgps,
+            Some(FixType::FloatRtk) => FixState::RtkFloat,
+            Some(FixType::Rtk) => FixState::RtkFixed,
+            Some(FixType::Invalid)
+            | Some(FixType::Estimated)
+            | Some(FixType::Manual)
+            | Some(FixType::Simulation)
+            | None => FixState::NoFix,
+        }
+    }
+
+    pub fn has_fix(self) -> bool {
+        self != FixState::NoFix
+    }
+}
+
+impl fmt::Display for FixState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixState::NoFix => write!(f, "No fix"),
+            FixState::Gps => write!(f, "GPS"),
+            FixState::Dgps => write!(f, "DGPS"),
+            FixState::RtkFloat => write!(f, "RTK float"),
+            FixState::RtkFixed => write!(f, "RTK fixed"),
+        }
+    }
+}
+
+/// A change in fix quality
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixTransition {
+    pub from: FixState,
+    pub to: FixState,
+    /// How long we were in `from`
+    pub duration: Duration,
+    /// Set when this is the first fix since startup
+    pub time_to_first_fix: Option<Duration>,
+    /// Set when this is the first RTK fixed since startup
+    pub time_to_rtk_fixed: Option<Duration>,
+}
+
+impl FixTransition {
+    /// Losing the fix, or dropping out of RTK fixed
+    pub fn is_alert(&self) -> bool {
+        self.to < self.from && (!self.to.has_fix() || self.from == FixState::RtkFixed)
+    }
+
+    /// For the log, e.g. "RTK float -> RTK fixed after 12.0 s (time to RTK fixed 42.5 s)"
+    pub fn message(&self) -> String {
+        let mut message = format!(
+            "{} -> {} after {:.1} s",
+            self.from,
+            self.to,
+            self.duration.as_secs_f64()
+        );
+        if let Some(ttff) = self.time_to_first_fix {
+            message.push_str(&format!(" (time to first fix {:.1} s)", ttff.as_secs_f64()));
+        }
+        if let Some(ttrf) = self.time_to_rtk_fixed {
+            message.push_str(&format!(" (time to RTK fixed {:.1} s)", ttrf.as_secs_f64()));
+        }
+        message
+    }
+}
+
+#[derive(Debug)]
+pub struct FixTracker {
+    started: Instant,
+    state: FixState,
+    since: Instant,
+    last_transition: Option<FixTransition>,
+    pub time_to_first_fix: Option<Duration>,
+    pub time_to_rtk_fixed: Option<Duration>,
+    /// Times a fix of any kind was lost
+    pub fix_losses: u32,
+    /// Times we dropped from RTK fixed to something worse
+    pub rtk_fixed_losses: u32,
+}
+
+impl FixTracker {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            state: FixState::NoFix,
+            since: now,
+            last_transition: None,
+            time_to_first_fix: None,
+            time_to_rtk_fixed: None,
+            fix_losses: 0,
+            rtk_fixed_losses: 0,
+        }
+    }
+
+    pub fn state(&self) -> FixState {
+        self.state
+    }
+
+    /// How long we've been in the current state
+    pub fn time_in_state(&self, now: Instant) -> Duration {
+        now.duration_since(self.since)
+    }
+
+    /// Record the fix type from the latest GGA. Returns the transition if
+    /// the fix quality changed.
+    pub fn update(&mut self, fix_type: Option<FixType>, now: Instant) -> Option<FixTransition> {
+        let state = FixState::from_fix_type(fix_type);
+        if state == self.state {
+            return None;
+        }
+
+        let mut transition = FixTransition {
+            from: self.state,
+            to: state,
+            duration: self.time_in_state(now),
+            time_to_first_fix: None,
+            time_to_rtk_fixed: None,
+        };
+        if state.has_fix() && self.time_to_first_fix.is_none() {
+            self.time_to_first_fix = Some(now.duration_since(self.started));
+            transition.time_to_first_fix = self.time_to_first_fix;
+        }
+        if state == FixState::RtkFixed && self.time_to_rtk_fixed.is_none() {
+            self.time_to_rtk_fixed = Some(now.duration_since(self.started));
+            transition.time_to_rtk_fixed = self.time_to_rtk_fixed;
+        }
+        if self.state.has_fix() && !state.has_fix() {
+            self.fix_losses += 1;
+        }
+        if self.state == FixState::RtkFixed {
+            self.rtk_fixed_losses += 1;
+        }
+
+        self.state = state;
+        self.since = now;
+        self.last_transition = Some(transition.clone());
+        Some(transition)
+    }
+
+    /// A warning to show while we're degraded after losing the fix or RTK
+    /// fixed, e.g. "LOST RTK FIXED - NOW RTK FLOAT"
+    pub fn alert(&self) -> Option<String> {
+        let transition = self.last_transition.as_ref()?;
+        if !transition.is_alert() {
+            return None;
+        }
+        let alert = if transition.to.has_fix() {
+            format!("LOST {} - NOW {}", transition.from, transition.to)
+        } else {
+            format!("LOST FIX (was {})", transition.from)
+        };
+        Some(alert.to_uppercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fix_state() {
+        assert_eq!(FixState::from_fix_type(None), FixState::NoFix);
+        assert_eq!(
+            FixState::from_fix_type(Some(FixType::Simulation)),
+            FixState::NoFix
+        );
+        assert_eq!(
+            FixState::from_fix_type(Some(FixType::FloatRtk)),
+            FixState::RtkFloat
+        );
+        assert!(FixState::RtkFixed > FixState::RtkFloat);
+        assert!(FixState::Dgps > FixState::Gps);
+    }
+
+    #[test]
+    fn test_transitions() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut tracker = FixTracker::new(start);
+
+        assert_eq!(tracker.update(None, at(1)), None);
+        let first = tracker.update(Some(FixType::Gps), at(20)).unwrap();
+        assert_eq!(first.time_to_first_fix, Some(Duration::from_secs(20)));
+        tracker.update(Some(FixType::FloatRtk), at(30));
+        let fixed = tracker.update(Some(FixType::Rtk), at(45)).unwrap();
+        assert_eq!(fixed.time_to_rtk_fixed, Some(Duration::from_secs(45)));
+        assert_eq!(fixed.duration, Duration::from_secs(15));
+        assert!(!fixed.is_alert());
+        assert_eq!(tracker.alert(), None);
+
+        let dropped = tracker.update(Some(FixType::FloatRtk), at(60)).unwrap();
+        assert!(dropped.is_alert());
+        assert_eq!(
+            tracker.alert().as_deref(),
+            Some("LOST RTK FIXED - NOW RTK FLOAT")
+        );
+        let lost = tracker.update(Some(FixType::Invalid), at(61)).unwrap();
+        assert!(lost.is_alert());
+        // Getting RTK fixed again doesn't reset the time to first fix
+        let again = tracker.update(Some(FixType::Rtk), at(90)).unwrap();
+        assert_eq!(again.time_to_first_fix, None);
+        assert_eq!(again.time_to_rtk_fixed, None);
+        assert_eq!(tracker.alert(), None);
+
+        assert_eq!(tracker.fix_losses, 1);
+        assert_eq!(tracker.rtk_fixed_losses, 1);
+        assert_eq!(tracker.state(), FixState::RtkFixed);
+    }
+}
@@ -1,10 +1,222 @@
+use nmea::sentences::FixType;
 use nmea::{self, Nmea};
+use std::collections::BTreeMap;
+use std::fmt;
 
 pub fn build_parser() -> Nmea {
-    let parser = Nmea::default();
-    parser
+    Nmea::default()
 }
 
-pub fn parse_nmea_sentence(parser: &mut Nmea, input: &str) -> () {
-    let _ = parser.parse(input);
+/// Why a sentence didn't make it into the parser
+#[derive(Debug, Clone, PartialEq)]
+pub enum NmeaError {
+    /// Missing the leading `$` or the `*hh` checksum
+    Malformed,
+    /// The checksum didn't match, usually a noisy or loose cable
+    Checksum { calculated: u8, found: u8 },
+    /// The checksum was fine but the contents couldn't be parsed
+    Parse(String),
+    /// A valid sentence type the parser doesn't handle, e.g. proprietary ones
+    Unsupported,
+}
+
+impl fmt::Display for NmeaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NmeaError::Malformed => write!(f, "Malformed sentence"),
+            NmeaError::Checksum { calculated, found } => write!(
+                f,
+                "Checksum mismatch (calculated {:02X}, found {:02X})",
+                calculated, found
+            ),
+            NmeaError::Parse(e) => write!(f, "Parse error: {}", e),
+            NmeaError::Unsupported => write!(f, "Unsupported sentence"),
+        }
+    }
+}
+
+/// XOR of every character between `$` and `*`
+pub fn nmea_checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |checksum, b| checksum ^ b)
+}
+
+/// Check the `*hh` checksum at the end of a sentence
+pub fn validate_checksum(sentence: &str) -> Result<(), NmeaError> {
+    let sentence = sentence.trim();
+    let body = sentence.strip_prefix('$').ok_or(NmeaError::Malformed)?;
+    let (data, checksum) = body.rsplit_once('*').ok_or(NmeaError::Malformed)?;
+    let found = u8::from_str_radix(checksum, 16).map_err(|_| NmeaError::Malformed)?;
+    let calculated = nmea_checksum(data);
+    if calculated != found {
+        return Err(NmeaError::Checksum { calculated, found });
+    }
+    Ok(())
+}
+
+/// Validate a sentence and, if it checks out, feed it to the parser
+pub fn parse_nmea_sentence(parser: &mut Nmea, input: &str) -> Result<(), NmeaError> {
+    validate_checksum(input)?;
+    match parser.parse(input.trim()) {
+        Ok(_) => Ok(()),
+        Err(nmea::Error::Unsupported(_)) | Err(nmea::Error::Unknown(_)) => {
+            Err(NmeaError::Unsupported)
+        }
+        Err(e) => Err(NmeaError::Parse(e.to_string())),
+    }
+}
+
+/// Age of differential corrections in seconds from a GGA sentence. The nmea
+/// crate doesn't keep this field, so we pick it out ourselves. `None` if the
+/// rover isn't using corrections.
+pub fn gga_differential_age(sentence: &str) -> Option<f32> {
+    let data = sentence.trim().split('*').next()?;
+    data.split(',').nth(13)?.trim().parse().ok()
+}
+
+/// Fix quality from a GGA sentence. The parser's `fix_type` is also set by
+/// RMC, which can't tell RTK from autonomous fixes, so we read it from the
+/// GGA directly.
+pub fn gga_fix_type(sentence: &str) -> Option<FixType> {
+    let data = sentence.trim().split('*').next()?;
+    let quality = data.split(',').nth(6)?.trim().chars().next()?;
+    Some(FixType::from(quality))
+}
+
+/// Outcome counts for one talker and sentence type, e.g. "GNGGA"
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SentenceStats {
+    pub ok: u64,
+    pub checksum_errors: u64,
+    pub parse_errors: u64,
+    pub unsupported: u64,
+}
+
+impl SentenceStats {
+    pub fn errors(&self) -> u64 {
+        self.checksum_errors + self.parse_errors
+    }
+}
+
+/// Per-sentence-type statistics, so a flaky cable shows up as numbers
+#[derive(Debug, Default)]
+pub struct NmeaStats {
+    by_type: BTreeMap<String, SentenceStats>,
+}
+
+/// The address field of a sentence, e.g. "GNGGA" or "PUBX"
+fn sentence_type(sentence: &str) -> String {
+    let body = sentence.trim().trim_start_matches('$');
+    let end = body.find([',', '*']).unwrap_or(body.len());
+    body[..end].chars().take(10).collect()
+}
+
+impl NmeaStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, sentence: &str, result: &Result<(), NmeaError>) {
+        // A corrupted address would create a new entry per error, so bad
+        // sentences are only counted under their address if it still looks
+        // like one, e.g. "GNGGA"
+        let key = match result {
+            Err(NmeaError::Malformed) | Err(NmeaError::Checksum { .. }) => {
+                let address = sentence_type(sentence);
+                if address.len() == 5
+                    && address
+                        .bytes()
+                        .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+                {
+                    address
+                } else {
+                    "corrupt".to_string()
+                }
+            }
+            _ => sentence_type(sentence),
+        };
+        let stats = self.by_type.entry(key).or_default();
+        match result {
+            Ok(()) => stats.ok += 1,
+            Err(NmeaError::Malformed) | Err(NmeaError::Checksum { .. }) => {
+                stats.checksum_errors += 1
+            }
+            Err(NmeaError::Parse(_)) => stats.parse_errors += 1,
+            Err(NmeaError::Unsupported) => stats.unsupported += 1,
+        }
+    }
+
+    pub fn get(&self, sentence_type: &str) -> Option<&SentenceStats> {
+        self.by_type.get(sentence_type)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &SentenceStats)> {
+        self.by_type
+            .iter()
+            .map(|(key, stats)| (key.as_str(), stats))
+    }
+
+    pub fn total_errors(&self) -> u64 {
+        self.by_type.values().map(SentenceStats::errors).sum()
+    }
+
+    /// One line listing every sentence type, e.g. "GNGGA 120 ok 1 bad"
+    pub fn summary(&self) -> String {
+        self.iter()
+            .map(|(key, stats)| format!("{} {} ok {} bad", key, stats.ok, stats.errors()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GGA: &str = "$GNGGA,230206.000,3546.091508,N,07840.396694,W,4,33,0.73,108.098,M,-33.026,M,1.0,0000*67\r\n";
+
+    #[test]
+    fn test_gga_differential_age() {
+        assert_eq!(gga_differential_age(GGA), Some(1.0));
+        let autonomous = "$GNGGA,123519.00,3546.8,N,07838.4,W,1,12,0.6,95.1,M,-33.0,M,,*74";
+        assert_eq!(gga_differential_age(autonomous), None);
+        assert_eq!(gga_fix_type(GGA), Some(FixType::Rtk));
+        assert_eq!(gga_fix_type(autonomous), Some(FixType::Gps));
+    }
+
+    #[test]
+    fn test_checksum_validation() {
+        let mut parser = build_parser();
+        assert_eq!(parse_nmea_sentence(&mut parser, GGA), Ok(()));
+        assert_eq!(parser.fix_satellites(), Some(33));
+
+        let corrupt = GGA.replace("3546.09", "3546.19");
+        assert_eq!(
+            parse_nmea_sentence(&mut parser, &corrupt),
+            Err(NmeaError::Checksum {
+                calculated: 0x66,
+                found: 0x67
+            })
+        );
+        assert_eq!(
+            parse_nmea_sentence(&mut parser, "$GNGGA,2302"),
+            Err(NmeaError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut parser = build_parser();
+        let mut stats = NmeaStats::new();
+        let corrupt = GGA.replace("3546.09", "3546.19");
+        let garbled = GGA.replace("GNGGA", "GN\u{1}GA");
+        for sentence in [GGA, GGA, corrupt.as_str(), garbled.as_str()] {
+            let result = parse_nmea_sentence(&mut parser, sentence);
+            stats.record(sentence, &result);
+        }
+        // The bad checksum is counted against the sentence it came from
+        let gga = stats.get("GNGGA").unwrap();
+        assert_eq!((gga.ok, gga.checksum_errors), (2, 1));
+        assert_eq!(stats.get("corrupt").unwrap().checksum_errors, 1);
+        assert_eq!(stats.total_errors(), 2);
+    }
 }
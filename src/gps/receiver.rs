@@ -0,0 +1,470 @@
+// src/gps/receiver.rs
+//! Configure the GPS receiver at startup instead of with a separate tool.
+//!
+//! Quectel receivers (e.g. the LC29H) take proprietary `$PAIR` sentences and
+//! answer each one with `$PAIR001`, u-blox receivers take UBX CFG messages and
+//! answer with ACK-ACK or ACK-NAK.
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind, Write};
+use std::time::{Duration, Instant};
+
+use super::demux::GpsFrame;
+use super::parser::nmea_checksum;
+use super::ubx;
+use crate::gps_serial::SerialReader;
+use crate::rtcm::messages::Constellation;
+
+/// How long to wait for the receiver to acknowledge a command
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+const CLASS_ACK: u8 = 0x05;
+const ACK_NAK: u8 = 0x00;
+const ACK_ACK: u8 = 0x01;
+const CLASS_CFG: u8 = 0x06;
+const CFG_MSG: u8 = 0x01;
+const CFG_RATE: u8 = 0x08;
+const CFG_NAV5: u8 = 0x24;
+const CFG_VALSET: u8 = 0x8A;
+/// Class of the standard NMEA messages in CFG-MSG
+const CLASS_NMEA: u8 = 0xF0;
+
+/// NMEA sentences the receivers can turn on and off, in the order of the
+/// `$PAIR062` type numbers and the u-blox NMEA message ids
+const NMEA_SENTENCES: [&str; 6] = ["GGA", "GLL", "GSA", "GSV", "RMC", "VTG"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceiverProtocol {
+    /// Quectel `$PAIR` commands
+    #[default]
+    Pair,
+    /// u-blox UBX CFG messages
+    Ubx,
+}
+
+/// What kind of motion the receiver's filter should expect
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DynamicModel {
+    #[default]
+    Portable,
+    Stationary,
+    Pedestrian,
+    Automotive,
+    Sea,
+    Airborne,
+}
+
+/// A named set of receiver settings
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ReceiverProfile {
+    /// Navigation solutions per second
+    pub rate_hz: u16,
+    /// NMEA sentences to output, e.g. ["GGA", "RMC"]. Others are turned off.
+    pub sentences: Vec<String>,
+    /// UBX messages to output (u-blox only), e.g. ["NAV-PVT"]
+    pub ubx_messages: Vec<String>,
+    pub constellations: Vec<Constellation>,
+    pub dynamic_model: DynamicModel,
+}
+
+impl Default for ReceiverProfile {
+    fn default() -> Self {
+        Self {
+            rate_hz: 1,
+            sentences: ["GGA", "RMC", "GSA", "GSV"].map(String::from).to_vec(),
+            ubx_messages: Vec::new(),
+            constellations: vec![
+                Constellation::Gps,
+                Constellation::Glonass,
+                Constellation::Galileo,
+                Constellation::BeiDou,
+                Constellation::Qzss,
+            ],
+            dynamic_model: DynamicModel::Portable,
+        }
+    }
+}
+
+/// Profiles that are always available, and can be overridden in the config
+/// file
+pub fn builtin_profiles() -> BTreeMap<String, ReceiverProfile> {
+    let mut profiles = BTreeMap::new();
+    profiles.insert(
+        "boat".to_string(),
+        ReceiverProfile {
+            rate_hz: 5,
+            dynamic_model: DynamicModel::Sea,
+            ..ReceiverProfile::default()
+        },
+    );
+    profiles.insert(
+        "survey".to_string(),
+        ReceiverProfile {
+            rate_hz: 1,
+            dynamic_model: DynamicModel::Stationary,
+            ..ReceiverProfile::default()
+        },
+    );
+    profiles
+}
+
+/// The `[receiver]` section of the config file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ReceiverConfig {
+    pub protocol: ReceiverProtocol,
+    /// Profile to apply at startup
+    pub profile: Option<String>,
+    /// Extra profiles, or replacements for the built-in ones
+    pub profiles: BTreeMap<String, ReceiverProfile>,
+}
+
+impl ReceiverConfig {
+    pub fn find_profile(&self, name: &str) -> Option<ReceiverProfile> {
+        self.profiles
+            .get(name)
+            .cloned()
+            .or_else(|| builtin_profiles().remove(name))
+    }
+}
+
+/// Build a proprietary sentence such as `$PAIR050,200*21\r\n` from the part
+/// between `$` and `*`
+pub fn proprietary_sentence(body: &str) -> String {
+    format!("${}*{:02X}\r\n", body, nmea_checksum(body))
+}
+
+/// How the receiver will acknowledge a command
+#[derive(Debug, Clone, PartialEq)]
+enum AckMatch {
+    /// `$PAIR001,<command>,<result>`
+    Pair(u16),
+    /// ACK-ACK or ACK-NAK for this class and id
+    Ubx(u8, u8),
+}
+
+/// A single command, ready to send
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceiverCommand {
+    pub description: String,
+    pub bytes: Vec<u8>,
+    ack: AckMatch,
+}
+
+impl ReceiverCommand {
+    fn pair(id: u16, args: &str, description: String) -> Self {
+        let body = format!("PAIR{:03},{}", id, args);
+        Self {
+            description,
+            bytes: proprietary_sentence(&body).into_bytes(),
+            ack: AckMatch::Pair(id),
+        }
+    }
+
+    fn ubx(id: u8, payload: &[u8], description: String) -> Self {
+        Self {
+            description,
+            bytes: ubx::build_frame(CLASS_CFG, id, payload),
+            ack: AckMatch::Ubx(CLASS_CFG, id),
+        }
+    }
+
+    /// `Some(true)` if `frame` acknowledges this command, `Some(false)` if it
+    /// rejects it and `None` if it's unrelated
+    fn check_ack(&self, frame: &GpsFrame) -> Option<bool> {
+        match (&self.ack, frame) {
+            (AckMatch::Pair(id), GpsFrame::Nmea(sentence)) => {
+                let body = sentence.trim().strip_prefix("$PAIR001,")?;
+                let body = body.split('*').next()?;
+                let mut fields = body.split(',');
+                if fields.next()?.parse::<u16>().ok()? != *id {
+                    return None;
+                }
+                match fields.next()? {
+                    "0" => Some(true),
+                    // Still being processed, another answer follows
+                    "1" => None,
+                    _ => Some(false),
+                }
+            }
+            (AckMatch::Ubx(class, id), GpsFrame::Ubx(frame)) => {
+                if frame.class() != CLASS_ACK || frame.payload().get(..2)? != [*class, *id] {
+                    return None;
+                }
+                match frame.id() {
+                    ACK_ACK => Some(true),
+                    ACK_NAK => Some(false),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn pair_nav_mode(model: DynamicModel) -> u8 {
+    match model {
+        DynamicModel::Portable | DynamicModel::Automotive | DynamicModel::Sea => 0,
+        DynamicModel::Pedestrian => 1,
+        DynamicModel::Airborne => 2,
+        DynamicModel::Stationary => 4,
+    }
+}
+
+fn ubx_dynamic_model(model: DynamicModel) -> u8 {
+    match model {
+        DynamicModel::Portable => 0,
+        DynamicModel::Stationary => 2,
+        DynamicModel::Pedestrian => 3,
+        DynamicModel::Automotive => 4,
+        DynamicModel::Sea => 5,
+        DynamicModel::Airborne => 6,
+    }
+}
+
+/// CFG-SIGNAL enable keys for CFG-VALSET (generation 9 receivers)
+fn ubx_signal_key(constellation: Constellation) -> u32 {
+    match constellation {
+        Constellation::Gps => 0x1031_001F,
+        Constellation::Sbas => 0x1031_0020,
+        Constellation::Galileo => 0x1031_0021,
+        Constellation::BeiDou => 0x1031_0022,
+        Constellation::Qzss => 0x1031_0024,
+        Constellation::Glonass => 0x1031_0025,
+    }
+}
+
+fn ubx_message_id(name: &str) -> Option<(u8, u8)> {
+    match name.to_ascii_uppercase().as_str() {
+        "NAV-PVT" => Some((ubx::CLASS_NAV, ubx::NAV_PVT)),
+        "NAV-HPPOSLLH" => Some((ubx::CLASS_NAV, ubx::NAV_HPPOSLLH)),
+        "NAV-RELPOSNED" => Some((ubx::CLASS_NAV, ubx::NAV_RELPOSNED)),
+        "NAV-SAT" => Some((ubx::CLASS_NAV, ubx::NAV_SAT)),
+        _ => None,
+    }
+}
+
+fn enabled(profile: &ReceiverProfile, sentence: &str) -> bool {
+    profile
+        .sentences
+        .iter()
+        .any(|s| s.eq_ignore_ascii_case(sentence))
+}
+
+/// The commands that put the receiver into `profile`
+pub fn profile_commands(
+    protocol: ReceiverProtocol,
+    profile: &ReceiverProfile,
+) -> Vec<ReceiverCommand> {
+    let interval_ms = 1000 / profile.rate_hz.clamp(1, 20);
+    let mut commands = Vec::new();
+
+    match protocol {
+        ReceiverProtocol::Pair => {
+            commands.push(ReceiverCommand::pair(
+                50,
+                &interval_ms.to_string(),
+                format!("Fix interval {} ms", interval_ms),
+            ));
+            for (kind, sentence) in NMEA_SENTENCES.iter().enumerate() {
+                let rate = enabled(profile, sentence) as u8;
+                commands.push(ReceiverCommand::pair(
+                    62,
+                    &format!("{},{}", kind, rate),
+                    format!("{} output {}", sentence, rate),
+                ));
+            }
+            // GPS, GLONASS, Galileo, BeiDou, QZSS and NavIC flags
+            let systems = [
+                Constellation::Gps,
+                Constellation::Glonass,
+                Constellation::Galileo,
+                Constellation::BeiDou,
+                Constellation::Qzss,
+            ];
+            let mut flags: Vec<String> = systems
+                .iter()
+                .map(|c| (profile.constellations.contains(c) as u8).to_string())
+                .collect();
+            flags.push("0".to_string());
+            commands.push(ReceiverCommand::pair(
+                66,
+                &flags.join(","),
+                "Constellations".to_string(),
+            ));
+            commands.push(ReceiverCommand::pair(
+                80,
+                &pair_nav_mode(profile.dynamic_model).to_string(),
+                format!("Dynamic model {:?}", profile.dynamic_model),
+            ));
+        }
+        ReceiverProtocol::Ubx => {
+            // Measurement rate, one solution per measurement, GPS time
+            let mut payload = interval_ms.to_le_bytes().to_vec();
+            payload.extend_from_slice(&[1, 0, 1, 0]);
+            commands.push(ReceiverCommand::ubx(
+                CFG_RATE,
+                &payload,
+                format!("Measurement rate {} ms", interval_ms),
+            ));
+
+            for (id, sentence) in NMEA_SENTENCES.iter().enumerate() {
+                let rate = enabled(profile, sentence) as u8;
+                commands.push(ReceiverCommand::ubx(
+                    CFG_MSG,
+                    &[CLASS_NMEA, id as u8, rate],
+                    format!("{} output {}", sentence, rate),
+                ));
+            }
+            for name in &profile.ubx_messages {
+                if let Some((class, id)) = ubx_message_id(name) {
+                    commands.push(ReceiverCommand::ubx(
+                        CFG_MSG,
+                        &[class, id, 1],
+                        format!("{} output 1", name),
+                    ));
+                }
+            }
+
+            // Version, RAM layer, reserved, then key/value pairs
+            let mut payload = vec![0, 0x01, 0, 0];
+            for constellation in [
+                Constellation::Gps,
+                Constellation::Sbas,
+                Constellation::Galileo,
+                Constellation::BeiDou,
+                Constellation::Qzss,
+                Constellation::Glonass,
+            ] {
+                payload.extend_from_slice(&ubx_signal_key(constellation).to_le_bytes());
+                payload.push(profile.constellations.contains(&constellation) as u8);
+            }
+            commands.push(ReceiverCommand::ubx(
+                CFG_VALSET,
+                &payload,
+                "Constellations".to_string(),
+            ));
+
+            // Only apply the dynamic model from the mask
+            let mut payload = vec![0u8; 36];
+            payload[0] = 0x01;
+            payload[2] = ubx_dynamic_model(profile.dynamic_model);
+            commands.push(ReceiverCommand::ubx(
+                CFG_NAV5,
+                &payload,
+                format!("Dynamic model {:?}", profile.dynamic_model),
+            ));
+        }
+    }
+
+    commands
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandResult {
+    Ack,
+    Nak,
+    Timeout,
+}
+
+/// Send one command and wait for the receiver to answer it. Anything else
+/// the receiver sends in the meantime is dropped.
+pub fn send_command(
+    port: &mut SerialReader,
+    command: &ReceiverCommand,
+) -> io::Result<CommandResult> {
+    port.write_all(&command.bytes)?;
+    port.flush()?;
+
+    let deadline = Instant::now() + ACK_TIMEOUT;
+    while Instant::now() < deadline {
+        let frames = match port.read_frames() {
+            Ok(frames) => frames,
+            Err(e) => {
+                let e = io::Error::from(e);
+                if e.kind() == ErrorKind::TimedOut {
+                    continue;
+                }
+                return Err(e);
+            }
+        };
+        for frame in &frames {
+            match command.check_ack(&frame.value) {
+                Some(true) => return Ok(CommandResult::Ack),
+                Some(false) => return Ok(CommandResult::Nak),
+                None => {}
+            }
+        }
+    }
+    Ok(CommandResult::Timeout)
+}
+
+/// Apply every setting in `profile`, returning how each command went
+pub fn apply_profile(
+    port: &mut SerialReader,
+    protocol: ReceiverProtocol,
+    profile: &ReceiverProfile,
+) -> io::Result<Vec<(ReceiverCommand, CommandResult)>> {
+    profile_commands(protocol, profile)
+        .into_iter()
+        .map(|command| {
+            let result = send_command(port, &command)?;
+            Ok((command, result))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proprietary_sentence() {
+        assert_eq!(proprietary_sentence("PAIR062,0,1"), "$PAIR062,0,1*3F\r\n");
+    }
+
+    #[test]
+    fn test_pair_ack() {
+        let commands = profile_commands(ReceiverProtocol::Pair, &ReceiverProfile::default());
+        let rate = &commands[0];
+        assert_eq!(rate.bytes, proprietary_sentence("PAIR050,1000").as_bytes());
+
+        let ack = |s: &str| GpsFrame::Nmea(proprietary_sentence(s));
+        assert_eq!(rate.check_ack(&ack("PAIR001,050,0")), Some(true));
+        assert_eq!(rate.check_ack(&ack("PAIR001,050,1")), None);
+        assert_eq!(rate.check_ack(&ack("PAIR001,050,4")), Some(false));
+        assert_eq!(rate.check_ack(&ack("PAIR001,062,0")), None);
+    }
+
+    #[test]
+    fn test_ubx_ack() {
+        let profile = builtin_profiles().remove("boat").unwrap();
+        let commands = profile_commands(ReceiverProtocol::Ubx, &profile);
+        let rate = &commands[0];
+        // 5 Hz is a 200 ms measurement rate
+        assert_eq!(
+            rate.bytes,
+            ubx::build_frame(CLASS_CFG, CFG_RATE, &[200, 0, 1, 0, 1, 0])
+        );
+
+        let reply = |id, payload: &[u8]| {
+            let data = ubx::build_frame(CLASS_ACK, id, payload);
+            match ubx::parse_frame(&data) {
+                ubx::UbxParse::Complete(frame) => GpsFrame::Ubx(frame),
+                other => panic!("Expected a frame, got {:?}", other),
+            }
+        };
+        assert_eq!(
+            rate.check_ack(&reply(ACK_ACK, &[CLASS_CFG, CFG_RATE])),
+            Some(true)
+        );
+        assert_eq!(
+            rate.check_ack(&reply(ACK_NAK, &[CLASS_CFG, CFG_RATE])),
+            Some(false)
+        );
+        assert_eq!(rate.check_ack(&reply(ACK_ACK, &[CLASS_CFG, CFG_MSG])), None);
+    }
+}
@@ -0,0 +1,383 @@
+// src/gps/satellites.rs
+//! Satellites in view and in use, per constellation, from GSV and GSA. The
+//! nmea crate keeps a single list of fix satellites, which each GNGSA from a
+//! multi-constellation receiver overwrites, so we read these ourselves.
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::rtcm::messages::Constellation;
+
+/// A satellite missing from the GSV output for this long has dropped out
+const DROPOUT_AFTER: Duration = Duration::from_secs(5);
+/// How often the per-constellation counts are added to the history
+const HISTORY_INTERVAL: Duration = Duration::from_secs(1);
+/// Ten minutes of history at one sample a second
+const HISTORY_LEN: usize = 600;
+
+/// Constellation from a talker ID, e.g. "GP". "GN" covers several systems, so
+/// it gives `None`.
+fn talker_constellation(talker: &str) -> Option<Constellation> {
+    match talker {
+        "GP" => Some(Constellation::Gps),
+        "GL" => Some(Constellation::Glonass),
+        "GA" => Some(Constellation::Galileo),
+        "GB" | "BD" => Some(Constellation::BeiDou),
+        "GQ" | "QZ" => Some(Constellation::Qzss),
+        _ => None,
+    }
+}
+
+/// Constellation from the NMEA 4.11 system ID at the end of a GSA
+fn system_id_constellation(id: &str) -> Option<Constellation> {
+    match id {
+        "1" => Some(Constellation::Gps),
+        "2" => Some(Constellation::Glonass),
+        "3" => Some(Constellation::Galileo),
+        "4" => Some(Constellation::BeiDou),
+        "5" => Some(Constellation::Qzss),
+        _ => None,
+    }
+}
+
+/// Constellation from an NMEA satellite number, for GNGSA sentences from
+/// receivers older than NMEA 4.11
+fn prn_constellation(prn: u16) -> Option<Constellation> {
+    match prn {
+        1..=32 => Some(Constellation::Gps),
+        33..=64 => Some(Constellation::Sbas),
+        65..=96 => Some(Constellation::Glonass),
+        _ => None,
+    }
+}
+
+/// The fields of a sentence between the `$` and the checksum
+fn sentence_fields(sentence: &str) -> Vec<&str> {
+    let data = sentence.trim().trim_start_matches('$');
+    let data = data.split('*').next().unwrap_or_default();
+    data.split(',').collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SatelliteView {
+    pub prn: u16,
+    pub elevation: Option<u8>,
+    pub azimuth: Option<u16>,
+    /// Signal strength in dB-Hz for each signal (e.g. L1 and L5) it's
+    /// tracked on, keyed by NMEA signal ID
+    pub snr: BTreeMap<String, u8>,
+    /// In the receiver's last GSA for this constellation
+    pub used: bool,
+    pub last_seen: Instant,
+}
+
+impl SatelliteView {
+    /// Strongest signal, if any is being tracked
+    pub fn best_snr(&self) -> Option<u8> {
+        self.snr.values().copied().max()
+    }
+}
+
+/// Counts for one constellation at a point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CountSample {
+    pub at: Instant,
+    pub in_view: usize,
+    pub used: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct ConstellationStatus {
+    satellites: BTreeMap<u16, SatelliteView>,
+    used: BTreeSet<u16>,
+    history: VecDeque<CountSample>,
+    /// Satellites that disappeared from view
+    pub dropouts: u32,
+}
+
+impl ConstellationStatus {
+    pub fn satellites(&self) -> impl Iterator<Item = &SatelliteView> {
+        self.satellites.values()
+    }
+
+    pub fn in_view(&self) -> usize {
+        self.satellites.len()
+    }
+
+    pub fn used(&self) -> usize {
+        self.satellites.values().filter(|sat| sat.used).count()
+    }
+
+    /// Average of each satellite's strongest signal
+    pub fn mean_snr(&self) -> Option<f32> {
+        let snrs: Vec<u8> = self
+            .satellites
+            .values()
+            .filter_map(SatelliteView::best_snr)
+            .collect();
+        if snrs.is_empty() {
+            return None;
+        }
+        Some(snrs.iter().map(|&snr| snr as f32).sum::<f32>() / snrs.len() as f32)
+    }
+
+    /// Satellites in view and in use over time, oldest first
+    pub fn history(&self) -> &VecDeque<CountSample> {
+        &self.history
+    }
+
+    fn set_used(&mut self, used: BTreeSet<u16>) {
+        for sat in self.satellites.values_mut() {
+            sat.used = used.contains(&sat.prn);
+        }
+        self.used = used;
+    }
+}
+
+/// A satellite that stopped showing up in the GSV output
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dropout {
+    pub constellation: Constellation,
+    pub prn: u16,
+    pub elevation: Option<u8>,
+    /// Whether it was part of the solution when it was last seen
+    pub was_used: bool,
+}
+
+#[derive(Debug)]
+pub struct SatelliteTracker {
+    constellations: BTreeMap<Constellation, ConstellationStatus>,
+    last_sample: Option<Instant>,
+}
+
+impl SatelliteTracker {
+    pub fn new() -> Self {
+        Self {
+            constellations: BTreeMap::new(),
+            last_sample: None,
+        }
+    }
+
+    /// Take in a GSV or GSA sentence. Anything else is ignored. Returns
+    /// whether the sentence was used.
+    pub fn record(&mut self, sentence: &str, now: Instant) -> bool {
+        let fields = sentence_fields(sentence);
+        let (talker, kind) = match fields.first() {
+            Some(address) if address.len() == 5 => address.split_at(2),
+            _ => return false,
+        };
+        match kind {
+            "GSV" => self.record_gsv(talker, &fields, now),
+            "GSA" => self.record_gsa(talker, &fields),
+            _ => false,
+        }
+    }
+
+    fn record_gsv(&mut self, talker: &str, fields: &[&str], now: Instant) -> bool {
+        let Some(constellation) = talker_constellation(talker) else {
+            return false;
+        };
+        let Some(groups) = fields.get(4..) else {
+            return false;
+        };
+        // NMEA 4.11 adds the signal ID after the satellites
+        let (groups, signal) = if groups.len() % 4 == 1 {
+            (&groups[..groups.len() - 1], groups[groups.len() - 1])
+        } else {
+            (groups, "")
+        };
+
+        let status = self.constellations.entry(constellation).or_default();
+        for group in groups.chunks_exact(4) {
+            let Ok(prn) = group[0].parse::<u16>() else {
+                continue;
+            };
+            let used = status.used.contains(&prn);
+            let sat = status.satellites.entry(prn).or_insert(SatelliteView {
+                prn,
+                elevation: None,
+                azimuth: None,
+                snr: BTreeMap::new(),
+                used,
+                last_seen: now,
+            });
+            sat.elevation = group[1].parse().ok();
+            sat.azimuth = group[2].parse().ok();
+            match group[3].parse() {
+                Ok(snr) => sat.snr.insert(signal.to_string(), snr),
+                // In view but this signal isn't being tracked
+                Err(_) => sat.snr.remove(signal),
+            };
+            sat.last_seen = now;
+        }
+        true
+    }
+
+    fn record_gsa(&mut self, talker: &str, fields: &[&str]) -> bool {
+        let Some(prns) = fields.get(3..15) else {
+            return false;
+        };
+        let prns: Vec<u16> = prns.iter().filter_map(|prn| prn.parse().ok()).collect();
+        let constellation = talker_constellation(talker)
+            .or_else(|| fields.get(18).and_then(|id| system_id_constellation(id)));
+
+        match constellation {
+            Some(constellation) => {
+                self.constellations
+                    .entry(constellation)
+                    .or_default()
+                    .set_used(prns.into_iter().collect());
+            }
+            // An old style GNGSA lists satellites from several systems
+            None => {
+                let mut by_constellation: BTreeMap<Constellation, BTreeSet<u16>> = BTreeMap::new();
+                for prn in prns {
+                    if let Some(constellation) = prn_constellation(prn) {
+                        by_constellation
+                            .entry(constellation)
+                            .or_default()
+                            .insert(prn);
+                    }
+                }
+                for (constellation, used) in by_constellation {
+                    self.constellations
+                        .entry(constellation)
+                        .or_default()
+                        .set_used(used);
+                }
+            }
+        }
+        true
+    }
+
+    /// Drop satellites that haven't been seen for a while and add to the
+    /// history. Returns the satellites that dropped out.
+    pub fn update(&mut self, now: Instant) -> Vec<Dropout> {
+        let mut dropouts = Vec::new();
+        for (&constellation, status) in &mut self.constellations {
+            status.satellites.retain(|_, sat| {
+                if now.duration_since(sat.last_seen) < DROPOUT_AFTER {
+                    return true;
+                }
+                dropouts.push(Dropout {
+                    constellation,
+                    prn: sat.prn,
+                    elevation: sat.elevation,
+                    was_used: sat.used,
+                });
+                false
+            });
+        }
+        for dropout in &dropouts {
+            if let Some(status) = self.constellations.get_mut(&dropout.constellation) {
+                status.dropouts += 1;
+            }
+        }
+
+        if self
+            .last_sample
+            .is_none_or(|last| now.duration_since(last) >= HISTORY_INTERVAL)
+        {
+            self.last_sample = Some(now);
+            for status in self.constellations.values_mut() {
+                let sample = CountSample {
+                    at: now,
+                    in_view: status.in_view(),
+                    used: status.used(),
+                };
+                status.history.push_back(sample);
+                if status.history.len() > HISTORY_LEN {
+                    status.history.pop_front();
+                }
+            }
+        }
+        dropouts
+    }
+
+    pub fn get(&self, constellation: Constellation) -> Option<&ConstellationStatus> {
+        self.constellations.get(&constellation)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Constellation, &ConstellationStatus)> {
+        self.constellations
+            .iter()
+            .map(|(&constellation, status)| (constellation, status))
+    }
+}
+
+impl Default for SatelliteTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // From field_tests/volleyball/3ft.log
+    const GSA: &str = "$GNGSA,A,3,04,07,08,09,16,26,27,31,,,,,1.40,0.73,1.19,1*0F\r\n";
+    const GSV: [&str; 5] = [
+        "$GPGSV,3,1,11,03,03,212,18,04,66,210,39,07,19,302,35,08,46,177,39,1*6A\r\n",
+        "$GPGSV,3,2,11,09,50,295,45,16,56,035,47,18,,,13,26,22,058,27,1*50\r\n",
+        "$GPGSV,3,3,11,27,62,120,37,31,19,102,27,44,32,233,24,1*5B\r\n",
+        "$GPGSV,2,1,06,03,03,212,21,04,66,210,48,08,46,177,36,09,50,295,48,8*61\r\n",
+        "$GPGSV,2,2,06,26,22,058,41,27,62,120,37,8*61\r\n",
+    ];
+    const GLONASS_GSV: &str =
+        "$GLGSV,2,1,08,65,52,256,41,66,29,321,36,72,24,190,17,74,20,056,27,1*77\r\n";
+
+    #[test]
+    fn test_gsv_and_gsa() {
+        let now = Instant::now();
+        let mut tracker = SatelliteTracker::new();
+        assert!(tracker.record(GSA, now));
+        for sentence in GSV {
+            assert!(tracker.record(sentence, now));
+        }
+        assert!(tracker.record(GLONASS_GSV, now));
+        assert!(!tracker.record("$GNGGA,123519.00,3546.8,N*00", now));
+
+        let gps = tracker.get(Constellation::Gps).unwrap();
+        assert_eq!(gps.in_view(), 11);
+        assert_eq!(gps.used(), 8);
+        let sat = gps.satellites().find(|sat| sat.prn == 4).unwrap();
+        assert_eq!(sat.elevation, Some(66));
+        assert_eq!(sat.azimuth, Some(210));
+        assert_eq!(sat.snr.get("1"), Some(&39));
+        assert_eq!(sat.best_snr(), Some(48));
+        assert!(sat.used);
+        let sat = gps.satellites().find(|sat| sat.prn == 18).unwrap();
+        assert_eq!(sat.elevation, None);
+        assert!(!sat.used);
+
+        let glonass = tracker.get(Constellation::Glonass).unwrap();
+        assert_eq!(glonass.in_view(), 4);
+        assert_eq!(glonass.used(), 0);
+    }
+
+    #[test]
+    fn test_dropouts() {
+        let start = Instant::now();
+        let mut tracker = SatelliteTracker::new();
+        tracker.record(GSA, start);
+        tracker.record(GSV[0], start);
+        assert!(tracker.update(start).is_empty());
+
+        // Satellite 3 sets, the others are still reported
+        let later = start + Duration::from_secs(6);
+        tracker.record(
+            "$GPGSV,3,1,11,04,66,210,39,07,19,302,35,08,46,177,39,1*52\r\n",
+            later,
+        );
+        let dropouts = tracker.update(later);
+        assert_eq!(dropouts.len(), 1);
+        assert_eq!(dropouts[0].prn, 3);
+        assert!(!dropouts[0].was_used);
+
+        let gps = tracker.get(Constellation::Gps).unwrap();
+        assert_eq!(gps.dropouts, 1);
+        assert_eq!(gps.in_view(), 3);
+        let history: Vec<usize> = gps.history().iter().map(|s| s.in_view).collect();
+        assert_eq!(history, [4, 3]);
+    }
+}
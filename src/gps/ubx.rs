@@ -0,0 +1,459 @@
+// src/gps/ubx.rs
+//! u-blox UBX binary protocol: framing and the navigation messages we use.
+
+/// Every UBX frame starts with these two bytes
+pub const SYNC: [u8; 2] = [0xB5, 0x62];
+/// Sync chars, class, id and the 16 bit payload length
+const HEADER_LEN: usize = 6;
+const CHECKSUM_LEN: usize = 2;
+/// Longer than any message we expect, so a corrupt length doesn't make us
+/// wait for kilobytes of data that will never form a frame
+const MAX_PAYLOAD_LEN: usize = 4096;
+
+pub const CLASS_NAV: u8 = 0x01;
+pub const NAV_PVT: u8 = 0x07;
+pub const NAV_HPPOSLLH: u8 = 0x14;
+pub const NAV_SAT: u8 = 0x35;
+pub const NAV_RELPOSNED: u8 = 0x3C;
+
+/// 8-bit Fletcher checksum over class, id, length and payload
+pub fn checksum(data: &[u8]) -> (u8, u8) {
+    let mut ck_a = 0u8;
+    let mut ck_b = 0u8;
+    for &byte in data {
+        ck_a = ck_a.wrapping_add(byte);
+        ck_b = ck_b.wrapping_add(ck_a);
+    }
+    (ck_a, ck_b)
+}
+
+/// Build a complete frame, e.g. to send a configuration message
+pub fn build_frame(class: u8, id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut data = SYNC.to_vec();
+    data.extend_from_slice(&[class, id]);
+    data.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    data.extend_from_slice(payload);
+    let (ck_a, ck_b) = checksum(&data[2..]);
+    data.extend_from_slice(&[ck_a, ck_b]);
+    data
+}
+
+/// A complete UBX frame that passed the checksum
+#[derive(Debug, Clone, PartialEq)]
+pub struct UbxFrame {
+    data: Vec<u8>,
+}
+
+impl UbxFrame {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn class(&self) -> u8 {
+        self.data[2]
+    }
+
+    pub fn id(&self) -> u8 {
+        self.data[3]
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data[HEADER_LEN..self.data.len() - CHECKSUM_LEN]
+    }
+}
+
+/// Result of looking for a frame at the start of a buffer
+#[derive(Debug, PartialEq)]
+pub enum UbxParse {
+    /// A valid frame, which is `as_bytes().len()` bytes long
+    Complete(UbxFrame),
+    /// Looks like the start of a frame, more data is needed
+    Incomplete,
+    /// Not a frame, or the checksum didn't match
+    Invalid,
+}
+
+/// Try to parse a UBX frame at the start of `buf`
+pub fn parse_frame(buf: &[u8]) -> UbxParse {
+    for (i, &sync) in SYNC.iter().enumerate() {
+        match buf.get(i) {
+            Some(&byte) if byte == sync => {}
+            Some(_) => return UbxParse::Invalid,
+            None => return UbxParse::Incomplete,
+        }
+    }
+    if buf.len() < HEADER_LEN {
+        return UbxParse::Incomplete;
+    }
+
+    let payload_len = u16::from_le_bytes([buf[4], buf[5]]) as usize;
+    if payload_len > MAX_PAYLOAD_LEN {
+        return UbxParse::Invalid;
+    }
+    let frame_len = HEADER_LEN + payload_len + CHECKSUM_LEN;
+    if buf.len() < frame_len {
+        return UbxParse::Incomplete;
+    }
+
+    let (ck_a, ck_b) = checksum(&buf[2..HEADER_LEN + payload_len]);
+    if (ck_a, ck_b) != (buf[frame_len - 2], buf[frame_len - 1]) {
+        return UbxParse::Invalid;
+    }
+    UbxParse::Complete(UbxFrame {
+        data: buf[..frame_len].to_vec(),
+    })
+}
+
+/// Little-endian field access by byte offset, as laid out in the u-blox
+/// interface description
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn u8(&self, offset: usize) -> u8 {
+        self.0[offset]
+    }
+
+    fn i8(&self, offset: usize) -> i8 {
+        self.0[offset] as i8
+    }
+
+    fn u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.0[offset], self.0[offset + 1]])
+    }
+
+    fn i16(&self, offset: usize) -> i16 {
+        self.u16(offset) as i16
+    }
+
+    fn u32(&self, offset: usize) -> u32 {
+        let bytes = &self.0[offset..offset + 4];
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn i32(&self, offset: usize) -> i32 {
+        self.u32(offset) as i32
+    }
+}
+
+/// RTK carrier phase solution status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CarrierSolution {
+    None,
+    Float,
+    Fixed,
+}
+
+impl CarrierSolution {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0x03 {
+            1 => CarrierSolution::Float,
+            2 => CarrierSolution::Fixed,
+            _ => CarrierSolution::None,
+        }
+    }
+}
+
+/// Navigation position velocity time solution (NAV-PVT)
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavPvt {
+    /// GPS time of week of the navigation epoch
+    pub itow_ms: u32,
+    /// 0 no fix, 1 dead reckoning, 2 2D, 3 3D, 4 GNSS + dead reckoning, 5 time only
+    pub fix_type: u8,
+    pub gnss_fix_ok: bool,
+    /// Differential corrections were applied
+    pub diff_soln: bool,
+    pub carrier_solution: CarrierSolution,
+    pub num_sv: u8,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Height above the ellipsoid in metres
+    pub height_m: f64,
+    /// Height above mean sea level in metres
+    pub height_msl_m: f64,
+    /// Horizontal and vertical accuracy estimates in metres
+    pub h_acc_m: f64,
+    pub v_acc_m: f64,
+    pub ground_speed_mps: f64,
+    pub heading_deg: f64,
+    pub pdop: f64,
+}
+
+/// High precision geodetic position (NAV-HPPOSLLH)
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavHpPosLlh {
+    pub itow_ms: u32,
+    /// The receiver flagged the position as invalid
+    pub invalid: bool,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub height_m: f64,
+    pub height_msl_m: f64,
+    pub h_acc_m: f64,
+    pub v_acc_m: f64,
+}
+
+/// Position of the rover relative to the base station (NAV-RELPOSNED)
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavRelPosNed {
+    pub itow_ms: u32,
+    pub ref_station_id: u16,
+    /// North, east and down offsets from the base in metres
+    pub north_m: f64,
+    pub east_m: f64,
+    pub down_m: f64,
+    /// Length and heading of the baseline (only sent by version 1)
+    pub length_m: Option<f64>,
+    pub heading_deg: Option<f64>,
+    pub acc_north_m: f64,
+    pub acc_east_m: f64,
+    pub acc_down_m: f64,
+    pub gnss_fix_ok: bool,
+    pub diff_soln: bool,
+    pub rel_pos_valid: bool,
+    pub carrier_solution: CarrierSolution,
+}
+
+/// One satellite from NAV-SAT
+#[derive(Debug, Clone, PartialEq)]
+pub struct SatelliteInfo {
+    /// 0 GPS, 1 SBAS, 2 Galileo, 3 BeiDou, 5 QZSS, 6 GLONASS
+    pub gnss_id: u8,
+    pub sv_id: u8,
+    /// Carrier to noise ratio in dB-Hz
+    pub cno: u8,
+    pub elevation_deg: i8,
+    pub azimuth_deg: i16,
+    /// 0 no signal up to 7 code and carrier locked
+    pub quality: u8,
+    /// Used in the navigation solution
+    pub used: bool,
+    /// Differential corrections are available for this satellite
+    pub diff_corr: bool,
+}
+
+/// Satellite information (NAV-SAT)
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavSat {
+    pub itow_ms: u32,
+    pub satellites: Vec<SatelliteInfo>,
+}
+
+impl NavSat {
+    pub fn used_count(&self) -> usize {
+        self.satellites.iter().filter(|sat| sat.used).count()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UbxMessage {
+    NavPvt(NavPvt),
+    NavHpPosLlh(NavHpPosLlh),
+    NavRelPosNed(NavRelPosNed),
+    NavSat(NavSat),
+    Other { class: u8, id: u8 },
+}
+
+impl UbxMessage {
+    /// Name as used in the u-blox documentation, e.g. "NAV-PVT"
+    pub fn name(&self) -> String {
+        match self {
+            UbxMessage::NavPvt(_) => "NAV-PVT".to_string(),
+            UbxMessage::NavHpPosLlh(_) => "NAV-HPPOSLLH".to_string(),
+            UbxMessage::NavRelPosNed(_) => "NAV-RELPOSNED".to_string(),
+            UbxMessage::NavSat(_) => "NAV-SAT".to_string(),
+            UbxMessage::Other { class, id } => format!("{:02X}-{:02X}", class, id),
+        }
+    }
+}
+
+fn decode_nav_pvt(payload: &[u8]) -> Option<NavPvt> {
+    if payload.len() < 92 {
+        return None;
+    }
+    let f = Fields(payload);
+    let flags = f.u8(21) as u32;
+    Some(NavPvt {
+        itow_ms: f.u32(0),
+        fix_type: f.u8(20),
+        gnss_fix_ok: flags & 0x01 != 0,
+        diff_soln: flags & 0x02 != 0,
+        carrier_solution: CarrierSolution::from_bits(flags >> 6),
+        num_sv: f.u8(23),
+        longitude: f.i32(24) as f64 * 1e-7,
+        latitude: f.i32(28) as f64 * 1e-7,
+        height_m: f.i32(32) as f64 * 1e-3,
+        height_msl_m: f.i32(36) as f64 * 1e-3,
+        h_acc_m: f.u32(40) as f64 * 1e-3,
+        v_acc_m: f.u32(44) as f64 * 1e-3,
+        ground_speed_mps: f.i32(60) as f64 * 1e-3,
+        heading_deg: f.i32(64) as f64 * 1e-5,
+        pdop: f.u16(76) as f64 * 0.01,
+    })
+}
+
+fn decode_nav_hpposllh(payload: &[u8]) -> Option<NavHpPosLlh> {
+    if payload.len() < 36 {
+        return None;
+    }
+    let f = Fields(payload);
+    // Each value is split into a standard and a high precision part
+    Some(NavHpPosLlh {
+        invalid: f.u8(3) & 0x01 != 0,
+        itow_ms: f.u32(4),
+        longitude: f.i32(8) as f64 * 1e-7 + f.i8(24) as f64 * 1e-9,
+        latitude: f.i32(12) as f64 * 1e-7 + f.i8(25) as f64 * 1e-9,
+        height_m: f.i32(16) as f64 * 1e-3 + f.i8(26) as f64 * 1e-4,
+        height_msl_m: f.i32(20) as f64 * 1e-3 + f.i8(27) as f64 * 1e-4,
+        h_acc_m: f.u32(28) as f64 * 1e-4,
+        v_acc_m: f.u32(32) as f64 * 1e-4,
+    })
+}
+
+fn decode_nav_relposned(payload: &[u8]) -> Option<NavRelPosNed> {
+    let f = Fields(payload);
+    // Version 0 (M8P) is shorter and has no baseline length or heading
+    let (hp, acc, flags_offset, length_m, heading_deg) = match payload.first()? {
+        0 if payload.len() >= 40 => (20, 24, 36, None, None),
+        1 if payload.len() >= 64 => {
+            let length = f.i32(20) as f64 * 1e-2 + f.i8(35) as f64 * 1e-4;
+            (32, 36, 60, Some(length), Some(f.i32(24) as f64 * 1e-5))
+        }
+        _ => return None,
+    };
+    let flags = f.u32(flags_offset);
+    Some(NavRelPosNed {
+        ref_station_id: f.u16(2),
+        itow_ms: f.u32(4),
+        north_m: f.i32(8) as f64 * 1e-2 + f.i8(hp) as f64 * 1e-4,
+        east_m: f.i32(12) as f64 * 1e-2 + f.i8(hp + 1) as f64 * 1e-4,
+        down_m: f.i32(16) as f64 * 1e-2 + f.i8(hp + 2) as f64 * 1e-4,
+        length_m,
+        heading_deg,
+        acc_north_m: f.u32(acc) as f64 * 1e-4,
+        acc_east_m: f.u32(acc + 4) as f64 * 1e-4,
+        acc_down_m: f.u32(acc + 8) as f64 * 1e-4,
+        gnss_fix_ok: flags & 0x01 != 0,
+        diff_soln: flags & 0x02 != 0,
+        rel_pos_valid: flags & 0x04 != 0,
+        carrier_solution: CarrierSolution::from_bits(flags >> 3),
+    })
+}
+
+fn decode_nav_sat(payload: &[u8]) -> Option<NavSat> {
+    if payload.len() < 8 {
+        return None;
+    }
+    let f = Fields(payload);
+    let num_svs = f.u8(5) as usize;
+    if payload.len() < 8 + 12 * num_svs {
+        return None;
+    }
+    let satellites = (0..num_svs)
+        .map(|i| {
+            let offset = 8 + 12 * i;
+            let flags = f.u32(offset + 8);
+            SatelliteInfo {
+                gnss_id: f.u8(offset),
+                sv_id: f.u8(offset + 1),
+                cno: f.u8(offset + 2),
+                elevation_deg: f.i8(offset + 3),
+                azimuth_deg: f.i16(offset + 4),
+                quality: (flags & 0x07) as u8,
+                used: flags & 0x08 != 0,
+                diff_corr: flags & 0x40 != 0,
+            }
+        })
+        .collect();
+    Some(NavSat {
+        itow_ms: f.u32(0),
+        satellites,
+    })
+}
+
+/// Decode the messages we understand. Returns `None` if a known message is
+/// too short to decode.
+pub fn decode(frame: &UbxFrame) -> Option<UbxMessage> {
+    let payload = frame.payload();
+    let message = match (frame.class(), frame.id()) {
+        (CLASS_NAV, NAV_PVT) => UbxMessage::NavPvt(decode_nav_pvt(payload)?),
+        (CLASS_NAV, NAV_HPPOSLLH) => UbxMessage::NavHpPosLlh(decode_nav_hpposllh(payload)?),
+        (CLASS_NAV, NAV_RELPOSNED) => UbxMessage::NavRelPosNed(decode_nav_relposned(payload)?),
+        (CLASS_NAV, NAV_SAT) => UbxMessage::NavSat(decode_nav_sat(payload)?),
+        (class, id) => UbxMessage::Other { class, id },
+    };
+    Some(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> UbxFrame {
+        match parse_frame(data) {
+            UbxParse::Complete(frame) => frame,
+            other => panic!("Expected a frame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_frame() {
+        // UBX-ACK-ACK for CFG-PRT as sent by a real receiver
+        let ack = [0xB5, 0x62, 0x05, 0x01, 0x02, 0x00, 0x06, 0x00, 0x0E, 0x37];
+        assert_eq!(build_frame(0x05, 0x01, &[0x06, 0x00]), ack);
+        let frame = parse(&ack);
+        assert_eq!((frame.class(), frame.id()), (0x05, 0x01));
+        assert_eq!(frame.payload(), &[0x06, 0x00]);
+
+        assert_eq!(parse_frame(&ack[..7]), UbxParse::Incomplete);
+        let mut bad = ack;
+        bad[9] ^= 0xFF;
+        assert_eq!(parse_frame(&bad), UbxParse::Invalid);
+        assert_eq!(parse_frame(b"$GNGGA"), UbxParse::Invalid);
+    }
+
+    #[test]
+    fn test_decode_nav_pvt() {
+        let mut payload = vec![0u8; 92];
+        payload[0..4].copy_from_slice(&123_000u32.to_le_bytes());
+        payload[20] = 3;
+        payload[21] = 0x01 | 0x02 | (2 << 6);
+        payload[23] = 18;
+        payload[24..28].copy_from_slice(&(-786_400_000i32).to_le_bytes());
+        payload[28..32].copy_from_slice(&357_800_000i32.to_le_bytes());
+        payload[32..36].copy_from_slice(&62_150i32.to_le_bytes());
+        payload[40..44].copy_from_slice(&14u32.to_le_bytes());
+
+        let frame = parse(&build_frame(CLASS_NAV, NAV_PVT, &payload));
+        let Some(UbxMessage::NavPvt(pvt)) = decode(&frame) else {
+            panic!("Expected NAV-PVT");
+        };
+        assert_eq!(pvt.itow_ms, 123_000);
+        assert!(pvt.gnss_fix_ok && pvt.diff_soln);
+        assert_eq!(pvt.carrier_solution, CarrierSolution::Fixed);
+        assert_eq!(pvt.num_sv, 18);
+        assert!((pvt.latitude - 35.78).abs() < 1e-9);
+        assert!((pvt.longitude + 78.64).abs() < 1e-9);
+        assert!((pvt.height_m - 62.15).abs() < 1e-9);
+        assert!((pvt.h_acc_m - 0.014).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_decode_nav_relposned() {
+        let mut payload = vec![0u8; 64];
+        payload[0] = 1;
+        payload[8..12].copy_from_slice(&1_234i32.to_le_bytes());
+        payload[32] = -5i8 as u8;
+        payload[20..24].copy_from_slice(&1_500i32.to_le_bytes());
+        payload[60..64].copy_from_slice(&(0x07u32 | (1 << 3)).to_le_bytes());
+
+        let frame = parse(&build_frame(CLASS_NAV, NAV_RELPOSNED, &payload));
+        let Some(UbxMessage::NavRelPosNed(rel)) = decode(&frame) else {
+            panic!("Expected NAV-RELPOSNED");
+        };
+        assert!((rel.north_m - 12.3395).abs() < 1e-9);
+        assert_eq!(rel.length_m, Some(15.0));
+        assert!(rel.rel_pos_valid);
+        assert_eq!(rel.carrier_solution, CarrierSolution::Float);
+    }
+}
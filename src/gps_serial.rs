@@ -1,29 +1,50 @@
-use serialport;
 use serialport::Error;
 use serialport::TTYPort;
 use std::io::Read;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use crate::baud;
+use crate::gps::demux::{GpsFrame, StreamDemuxer};
+use crate::gps::parser;
+use crate::timing::Timed;
 
 #[derive(Debug)]
 pub struct SerialReader {
     gps_port: TTYPort,
-    gps_buffer: String,
+    demuxer: StreamDemuxer,
+    last_gga: Option<String>,
+    baud_rate: u32,
 }
 
-pub fn open_port(port: PathBuf) -> Result<SerialReader, Error> {
+/// Open a GNSS receiver port with a short read timeout, so reads never
+/// hold up the main loop for long
+pub fn open_native(port: &Path, baud_rate: u32) -> Result<TTYPort, Error> {
+    serialport::new(port.to_string_lossy(), baud_rate)
+        .timeout(Duration::from_millis(10))
+        .open_native()
+}
+
+/// Open the GPS port at `baud_rate`, or detect the rate if it's `None`
+pub fn open_port(port: PathBuf, baud_rate: Option<u32>) -> Result<SerialReader, Error> {
     const BAUD_RATE: u32 = 115_200;
+    // Long enough for a couple of sentences from a receiver outputting at 1 Hz
+    const DETECT_LISTEN: Duration = Duration::from_millis(1500);
 
-    match serialport::new(port.to_string_lossy(), BAUD_RATE)
-        .timeout(std::time::Duration::from_millis(10))
-        .open_native()
-    {
+    let baud_rate = baud_rate.unwrap_or_else(|| {
+        baud::detect_baud_rate(&port, BAUD_RATE, DETECT_LISTEN, baud::gps_output_valid)
+            .unwrap_or(BAUD_RATE)
+    });
+
+    match open_native(&port, baud_rate) {
         Ok(gps_port) => {
-            println!("Successfully opened port {}", port.to_string_lossy());
+            println!("Successfully opened port {} at {} baud.", port.to_string_lossy(), baud_rate);
             Ok(SerialReader {
-                gps_port: gps_port,
-                gps_buffer: String::new(),
+                gps_port,
+                demuxer: StreamDemuxer::new(),
+                last_gga: None,
+                baud_rate,
             })
         }
         Err(e) => {
@@ -34,40 +55,28 @@ pub fn open_port(port: PathBuf) -> Result<SerialReader, Error> {
 }
 
 impl SerialReader {
-    pub fn read_sentences(&mut self) -> Result<Vec<String>, Error> {
+    /// Read whatever the receiver has sent and return the complete frames,
+    /// each with when its first and last bytes were read
+    pub fn read_frames(&mut self) -> Result<Vec<Timed<GpsFrame>>, Error> {
         let mut serial_buf: Vec<u8> = vec![0; 1024];
-        let mut sentences = Vec::new();
 
         match self.gps_port.read(serial_buf.as_mut_slice()) {
             Ok(t) if t > 0 => {
-                let text = String::from_utf8_lossy(&serial_buf[..t]).into_owned();
-                self.gps_buffer.push_str(&text);
-
-                loop {
-                    if let Some(start) = self.gps_buffer.find('$') {
-                        if let Some(end_pos) = self.gps_buffer[start..].find("\r\n") {
-                            let end = start + end_pos + 2;
-                            let sentence = self.gps_buffer[start..end].to_string();
-                            sentences.push(sentence.clone());
-
-                            // Remove the processed sentence from the buffer
-                            self.gps_buffer = self.gps_buffer[end..].to_string();
-                        } else {
-                            // Partial sentence starting with $, keep from $ onward
-                            self.gps_buffer = self.gps_buffer[start..].to_string();
-                            break;
-                        }
-                    } else {
-                        // No sentence start found, discard garbage
-                        self.gps_buffer.clear();
-                        break;
+                let frames = self.demuxer.push_at(&serial_buf[..t], Instant::now());
+                for frame in &frames {
+                    // A corrupted GGA would give the caster a wrong position
+                    if let GpsFrame::Nmea(sentence) = &frame.value
+                        && sentence.get(3..6) == Some("GGA")
+                        && parser::validate_checksum(sentence).is_ok()
+                    {
+                        self.last_gga = Some(sentence.clone());
                     }
                 }
-                Ok(sentences)
+                Ok(frames)
             }
             Ok(_) => {
                 // No data read
-                Ok(sentences)
+                Ok(Vec::new())
             }
             Err(e) => {
                 // eprintln!("Error reading from port: {:?}", e);
@@ -77,6 +86,23 @@ impl SerialReader {
     }
 }
 
+impl SerialReader {
+    /// The most recent valid GGA sentence read from the receiver, with its
+    /// `\r\n`
+    pub fn last_gga(&self) -> Option<&str> {
+        self.last_gga.as_deref()
+    }
+
+    pub fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+
+    /// Bytes from the receiver that weren't part of any recognized frame
+    pub fn unknown_bytes(&self) -> u64 {
+        self.demuxer.unknown_bytes
+    }
+}
+
 impl Write for SerialReader {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.gps_port.write(buf)
@@ -0,0 +1,16 @@
+// src/lib.rs
+pub mod baud;
+pub mod config;
+pub mod discovery;
+pub mod display;
+pub mod gps;
+pub mod gps_serial;
+pub mod local_base;
+pub mod logging;
+pub mod motor;
+pub mod ntrip;
+pub mod rtcm;
+pub mod serial_link;
+pub mod telemetry;
+pub mod timing;
+pub mod usb_serial;
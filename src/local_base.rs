@@ -0,0 +1,174 @@
+// src/local_base.rs
+use serde::Deserialize;
+use std::io::{self, ErrorKind, Read};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use crate::gps_serial;
+
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// If a TCP base sends nothing for this long we reconnect
+const TCP_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Our own base receiver on shore, used instead of NTRIP when there is no
+/// cellular coverage. Set either `serial_port` or `tcp_address`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LocalBaseConfig {
+    /// Serial port the base receiver outputs RTCM3 on, e.g. /dev/ttyUSB1
+    pub serial_port: Option<PathBuf>,
+    pub baud_rate: u32,
+    /// Raw TCP stream of RTCM3, e.g. a radio bridge at 192.168.4.1:9000
+    pub tcp_address: Option<String>,
+}
+
+impl Default for LocalBaseConfig {
+    fn default() -> Self {
+        Self {
+            serial_port: None,
+            baud_rate: 115_200,
+            tcp_address: None,
+        }
+    }
+}
+
+impl LocalBaseConfig {
+    pub fn is_configured(&self) -> bool {
+        self.serial_port.is_some() || self.tcp_address.is_some()
+    }
+
+    fn describe(&self) -> String {
+        match (&self.serial_port, &self.tcp_address) {
+            (Some(port), _) => port.to_string_lossy().into_owned(),
+            (None, Some(address)) => address.clone(),
+            (None, None) => "nothing".to_string(),
+        }
+    }
+}
+
+enum BaseSource {
+    Serial(serialport::TTYPort),
+    Tcp(TcpStream),
+}
+
+fn open_source(config: &LocalBaseConfig) -> io::Result<BaseSource> {
+    if let Some(port) = &config.serial_port {
+        let port = gps_serial::open_native(port, config.baud_rate)?;
+        return Ok(BaseSource::Serial(port));
+    }
+    if let Some(address) = &config.tcp_address {
+        return connect_tcp(address).map(BaseSource::Tcp);
+    }
+    Err(io::Error::new(
+        ErrorKind::InvalidInput,
+        "No local base serial port or TCP address configured",
+    ))
+}
+
+/// Connect to a TCP base, trying each address `address` resolves to. A
+/// radio bridge that is out of range doesn't hold us up for minutes.
+fn connect_tcp(address: &str) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(
+        ErrorKind::NotFound,
+        format!("Could not resolve {}", address),
+    );
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(TCP_READ_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Forward everything the base sends to `tx` until the source fails. Returns
+/// `Ok` only if the receiving end of the channel has gone away.
+fn forward(source: &mut BaseSource, tx: &Sender<Vec<u8>>) -> io::Result<()> {
+    let mut buf = [0u8; 1024];
+    loop {
+        let result = match source {
+            BaseSource::Serial(port) => port.read(&mut buf),
+            BaseSource::Tcp(stream) => stream.read(&mut buf),
+        };
+        let n = match result {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Local base stream closed",
+                ));
+            }
+            Ok(n) => n,
+            // The serial port times out every few ms when the base is quiet
+            Err(e)
+                if e.kind() == ErrorKind::TimedOut && matches!(source, BaseSource::Serial(_)) =>
+            {
+                continue;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        if tx.send(buf[..n].to_vec()).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Read RTCM3 from the local base and send it to `tx`, the same channel the
+/// NTRIP client feeds. Reopens the source whenever it fails.
+pub fn run_local_base(tx: Sender<Vec<u8>>, config: LocalBaseConfig) {
+    run(tx, config, RETRY_DELAY)
+}
+
+fn run(tx: Sender<Vec<u8>>, config: LocalBaseConfig, retry_delay: Duration) {
+    loop {
+        let result = open_source(&config).and_then(|mut source| {
+            println!("Reading corrections from local base {}", config.describe());
+            forward(&mut source, &tx)
+        });
+
+        match result {
+            Ok(()) => return,
+            Err(e) => eprintln!(
+                "Local base {} failed: {}. Retrying in {} seconds",
+                config.describe(),
+                e,
+                retry_delay.as_secs()
+            ),
+        }
+        thread::sleep(retry_delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_tcp_base_reconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = LocalBaseConfig {
+            tcp_address: Some(listener.local_addr().unwrap().to_string()),
+            ..LocalBaseConfig::default()
+        };
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || run(tx, config, Duration::from_millis(10)));
+
+        // The base drops the connection after each message
+        for data in [[0xD3, 0x00, 0x01], [0xD3, 0x00, 0x02]] {
+            let (mut base, _) = listener.accept().unwrap();
+            base.write_all(&data).unwrap();
+            assert_eq!(rx.recv_timeout(CONNECT_TIMEOUT).unwrap(), data);
+        }
+    }
+}
@@ -1,12 +1,18 @@
 // src/logger.rs
-use csv::Writer;
+use csv::{Writer, WriterBuilder};
 use serde::Serialize;
 use std::fs::File;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
 use std::time::{SystemTime, UNIX_EPOCH};
-use crate::usb_serial::SensorData;
+use crate::gps::ubx::{CarrierSolution, UbxMessage};
+use crate::motor::pid::MotorOutput;
+use crate::rtcm::framer::RtcmFrame;
+use crate::rtcm::messages::BaseStation;
+use crate::timing::{self, ArrivalTime};
+use crate::telemetry::{Schema, TelemetryRecord};
 
 
 // Helper macro to create structs with field names
@@ -44,67 +50,86 @@ make_struct_with_fields! {
 }
 make_struct_with_fields! {
     #[derive(Debug, Clone, Serialize)]
-    pub struct ArduinoSensorData {
-        motor_1_rpm: f32,
-        motor_2_rpm: f32,
-        motor_1_tot_rotations: f32,
-        motor_2_tot_rotations: f32,
-        time_ms: u32,
-        // roll: f32,
-        // pitch: f32,
-        // yaw: f32,
-        current_motor_1_ma: f32,
-        current_motor_2_ma: f32,
-        sonar_mm: f32,
-        tof1_mm: f32,
-        tof2_mm: f32,
-        // acc_x: f32,
-        // acc_y: f32,
-        // acc_z: f32,
+    pub struct BaseStationData {
+        station_id: u16,
+        ecef_x: f64,
+        ecef_y: f64,
+        ecef_z: f64,
+        antenna_height_m: Option<f64>,
+        baseline_m: Option<f64>,
+        base_satellites: String,
     }
 }
-
-// Wrapper structs for CSV serialization
-#[derive(Serialize)]
-struct NmeaLog {
-    timestamp_us: u64,
-    log_type: &'static str,
-    data: NmeaSentence,
+make_struct_with_fields! {
+    #[derive(Debug, Clone, Serialize)]
+    pub struct UbxData {
+        ubx_message: String,
+        itow_ms: u32,
+        ubx_latitude: Option<f64>,
+        ubx_longitude: Option<f64>,
+        height_m: Option<f64>,
+        h_acc_m: Option<f64>,
+        v_acc_m: Option<f64>,
+        carrier_solution: Option<String>,
+        num_sv: Option<usize>,
+        rel_north_m: Option<f64>,
+        rel_east_m: Option<f64>,
+        rel_down_m: Option<f64>,
+    }
 }
-
-#[derive(Serialize)]
-struct RtcmLog {
-    timestamp_us: u64,
-    log_type: &'static str,
-    data: RtcmData,
+make_struct_with_fields! {
+    #[derive(Debug, Clone, Serialize)]
+    pub struct MotorControlData {
+        motor: u8,
+        setpoint_rpm: f64,
+        measured_rpm: f64,
+        output_percent: f64,
+        p_term: f64,
+        i_term: f64,
+        d_term: f64,
+        ff_term: f64,
+        saturated: bool,
+    }
 }
-
-#[derive(Serialize)]
-struct SensorLog {
-    timestamp_us: u64,
-    log_type: &'static str,
-    data: ArduinoSensorData,
+make_struct_with_fields! {
+    #[derive(Debug, Clone, Serialize)]
+    pub struct EventData {
+        source: String,
+        message: String,
+    }
 }
 
-
 /// Represents different types of data that can be logged
 #[derive(Debug, Clone)]
 pub enum LogData {
     NmeaSentence(NmeaSentence),
     RtcmData(RtcmData),
-    SensorData(ArduinoSensorData),
+    SensorData(TelemetryRecord),
+    BaseStation(BaseStationData),
+    Ubx(UbxData),
+    MotorControl(MotorControlData),
+    Event(EventData),
+}
+
+/// A record along with when its data came off the serial port, if it did
+#[derive(Debug, Clone)]
+struct LogEntry {
+    data: LogData,
+    arrival: Option<ArrivalTime>,
 }
 
 /// Logger handle that can be cloned and sent to other threads
 #[derive(Clone)]
 pub struct Logger {
-    tx: Sender<LogData>,
+    tx: Sender<LogEntry>,
 }
 
 impl Logger {
     /// Create a new logger that writes to the specified file
     pub fn new(log_file_path: PathBuf) -> std::io::Result<Self> {
-        let (tx, rx) = mpsc::channel::<LogData>();
+        let (tx, rx) = mpsc::channel::<LogEntry>();
+        // Fix the monotonic epoch before any data is read
+        timing::epoch();
 
         // Spawn logging thread
         thread::spawn(move || {
@@ -116,74 +141,175 @@ impl Logger {
         Ok(Logger { tx })
     }
 
+    fn send(&self, data: LogData, arrival: Option<ArrivalTime>) {
+        let _ = self.tx.send(LogEntry { data, arrival });
+    }
+
     /// Log a NMEA sentence
-    pub fn log_nmea(&self, sentence: &str) {
+    pub fn log_nmea(&self, sentence: &str, arrival: Option<ArrivalTime>) {
         let data = NmeaSentence {
             sentence: sentence.trim().to_string(),
         };
-        let _ = self.tx.send(LogData::NmeaSentence(data));
+        self.send(LogData::NmeaSentence(data), arrival);
+    }
+
+    /// Log a single RTCM3 frame
+    pub fn log_rtcm(&self, frame: &RtcmFrame, arrival: Option<ArrivalTime>) {
+        self.send(LogData::RtcmData(rtcm_data(frame)), arrival);
     }
 
-    /// Log RTCM correction data
-    pub fn log_rtcm(&self, data: &[u8]) {
-        let message_type = extract_rtcm_message_type(data);
-        let data_hex = hex_encode(data);
-        let data = RtcmData {
-            message_type,
-            data_length: data.len(),
-            data_hex: data_hex,
+    /// Log a line of Arduino sensor values. The field names are written
+    /// in an `ARDUINO_FIELDS` row whenever they change.
+    pub fn log_sensor_data(&self, record: &TelemetryRecord, arrival: Option<ArrivalTime>) {
+        self.send(LogData::SensorData(record.clone()), arrival);
+    }
+
+    /// Log the base station position from RTCM 1005/1006, with the distance
+    /// to it from the rover if we have a fix
+    pub fn log_base_station(&self, base: &BaseStation, baseline_m: Option<f64>) {
+        let Some(position) = &base.position else {
+            return;
         };
-        let _ = self.tx.send(LogData::RtcmData(data));
+        let data = BaseStationData {
+            station_id: position.station_id,
+            ecef_x: position.ecef_x,
+            ecef_y: position.ecef_y,
+            ecef_z: position.ecef_z,
+            antenna_height_m: position.antenna_height,
+            baseline_m,
+            base_satellites: base.satellite_summary(),
+        };
+        self.send(LogData::BaseStation(data), None);
     }
 
-    pub fn log_sensor_data(&self, data: &SensorData) {
-        let data = ArduinoSensorData {
-            motor_1_rpm: data.motor_1_rpm,
-            motor_2_rpm: data.motor_2_rpm,
-            motor_1_tot_rotations: data.motor_1_tot_rotations,
-            motor_2_tot_rotations: data.motor_2_tot_rotations,
-            time_ms: data.time_ms,
-            // roll: data.roll,
-            // pitch: data.pitch,
-            // yaw: data.yaw,
-            current_motor_1_ma: data.current_motor_1_ma,
-            current_motor_2_ma: data.current_motor_2_ma,    
-            sonar_mm: data.sonar_mm,
-            tof1_mm: data.tof1_mm,
-            tof2_mm: data.tof2_mm,
+    /// Log a decoded UBX navigation message. Other UBX messages are skipped.
+    pub fn log_ubx(&self, message: &UbxMessage, arrival: Option<ArrivalTime>) {
+        let carrier = |solution: CarrierSolution| Some(format!("{:?}", solution));
+        let mut data = UbxData {
+            ubx_message: message.name(),
+            itow_ms: 0,
+            ubx_latitude: None,
+            ubx_longitude: None,
+            height_m: None,
+            h_acc_m: None,
+            v_acc_m: None,
+            carrier_solution: None,
+            num_sv: None,
+            rel_north_m: None,
+            rel_east_m: None,
+            rel_down_m: None,
         };
+        match message {
+            UbxMessage::NavPvt(pvt) => {
+                data.itow_ms = pvt.itow_ms;
+                data.ubx_latitude = Some(pvt.latitude);
+                data.ubx_longitude = Some(pvt.longitude);
+                data.height_m = Some(pvt.height_m);
+                data.h_acc_m = Some(pvt.h_acc_m);
+                data.v_acc_m = Some(pvt.v_acc_m);
+                data.carrier_solution = carrier(pvt.carrier_solution);
+                data.num_sv = Some(pvt.num_sv as usize);
+            }
+            UbxMessage::NavHpPosLlh(pos) => {
+                data.itow_ms = pos.itow_ms;
+                data.ubx_latitude = Some(pos.latitude);
+                data.ubx_longitude = Some(pos.longitude);
+                data.height_m = Some(pos.height_m);
+                data.h_acc_m = Some(pos.h_acc_m);
+                data.v_acc_m = Some(pos.v_acc_m);
+            }
+            UbxMessage::NavRelPosNed(rel) => {
+                data.itow_ms = rel.itow_ms;
+                data.carrier_solution = carrier(rel.carrier_solution);
+                data.rel_north_m = Some(rel.north_m);
+                data.rel_east_m = Some(rel.east_m);
+                data.rel_down_m = Some(rel.down_m);
+            }
+            UbxMessage::NavSat(sat) => {
+                data.itow_ms = sat.itow_ms;
+                data.num_sv = Some(sat.used_count());
+            }
+            UbxMessage::Other { .. } => return,
+        }
+        self.send(LogData::Ubx(data), arrival);
+    }
 
-        let _ = self.tx.send(LogData::SensorData(data));
+    /// Log what an RPM control loop did with a line of telemetry. Pass the
+    /// line's arrival time so the row lines up with its `ARDUINO` row.
+    pub fn log_motor_control(&self, output: &MotorOutput, arrival: Option<ArrivalTime>) {
+        let data = MotorControlData {
+            motor: output.motor,
+            setpoint_rpm: output.setpoint,
+            measured_rpm: output.measured,
+            output_percent: output.pid.output,
+            p_term: output.pid.proportional,
+            i_term: output.pid.integral,
+            d_term: output.pid.derivative,
+            ff_term: output.pid.feed_forward,
+            saturated: output.pid.saturated,
+        };
+        self.send(LogData::MotorControl(data), arrival);
+    }
+
+    /// Log a notable event, e.g. a connection state change
+    pub fn log_event(&self, source: &str, message: &str) {
+        let data = EventData {
+            source: source.to_string(),
+            message: message.to_string(),
+        };
+        self.send(LogData::Event(data), None);
     }
 }
 
 /// Main logging thread function
-fn run_logger(rx: Receiver<LogData>, log_file_path: PathBuf) -> std::io::Result<()> {
+fn run_logger(rx: Receiver<LogEntry>, log_file_path: PathBuf) -> std::io::Result<()> {
     let file = File::create(&log_file_path)?;
-    let mut writer = Writer::from_writer(file);
+    // Each log type only fills in its own columns, so rows differ in length
+    let mut writer = WriterBuilder::new().flexible(true).from_writer(file);
 
-    // Write CSV header
-    let sensor_data_fields = ArduinoSensorData::field_names();
+    // Write CSV header. The Arduino's fields depend on its firmware, so
+    // they're given in ARDUINO_FIELDS rows instead.
     let nmea_fields = NmeaSentence::field_names();
     let rtcm_fields = RtcmData::field_names();
+    let base_station_fields = BaseStationData::field_names();
+    let ubx_fields = UbxData::field_names();
+    let motor_control_fields = MotorControlData::field_names();
+    let event_fields = EventData::field_names();
 
     let mut fields = vec![
         "timestamp_ns".to_string(),
         "log_type".to_string(),
+        // Monotonic, counted from the epoch in the first row
+        "first_byte_ns".to_string(),
+        "last_byte_ns".to_string(),
     ];
     fields.extend(nmea_fields);
     fields.extend(rtcm_fields);
-    fields.extend(sensor_data_fields);
+    fields.extend(base_station_fields);
+    fields.extend(ubx_fields);
+    fields.extend(motor_control_fields);
+    fields.extend(event_fields);
 
     writer.write_record(&fields)?;
 
+    // Lets the monotonic arrival times be turned into wall clock time
+    let epoch = LogEntry {
+        data: LogData::Event(EventData {
+            source: "LOGGER".to_string(),
+            message: format!("Monotonic epoch {} ns", timing::epoch_unix_nanos()),
+        }),
+        arrival: None,
+    };
+    let mut arduino_schema = None;
+    write_log_entry(&mut writer, epoch, &mut arduino_schema)?;
+
     writer.flush()?;
 
     loop {
         match rx.recv() {
             Ok(data) => {
                 println!("Logging data: {:?}", data);
-                write_log_entry(&mut writer, data)?;
+                write_log_entry(&mut writer, data, &mut arduino_schema)?;
                 writer.flush()?;
             }
             Err(err) => {
@@ -198,14 +324,28 @@ fn run_logger(rx: Receiver<LogData>, log_file_path: PathBuf) -> std::io::Result<
     Ok(())
 }
 
-/// Write a single log entry to the CSV file
-fn write_log_entry(writer: &mut Writer<File>, data: LogData) -> csv::Result<()> {
+/// Write a single log entry to the CSV file. `arduino_schema` is the schema
+/// of the last Arduino row, to notice when the fields change.
+fn write_log_entry(
+    writer: &mut Writer<File>,
+    entry: LogEntry,
+    arduino_schema: &mut Option<Arc<Schema>>,
+) -> csv::Result<()> {
     let timestamp = get_timestamp_nanos();
-    match data {
+    let (first_byte, last_byte) = match entry.arrival {
+        Some(arrival) => (
+            timing::monotonic_nanos(arrival.first_byte).to_string(),
+            timing::monotonic_nanos(arrival.last_byte).to_string(),
+        ),
+        None => (String::new(), String::new()),
+    };
+    match entry.data {
         LogData::NmeaSentence(sentence) => {
             let record = vec![
                 timestamp.to_string(),
                 "NMEA".to_string(),
+                first_byte,
+                last_byte,
                 sentence.sentence,
             ];
             writer.write_record(record)?;
@@ -214,26 +354,99 @@ fn write_log_entry(writer: &mut Writer<File>, data: LogData) -> csv::Result<()>
             let record = vec![
                 timestamp.to_string(),
                 "RTCM".to_string(),
+                first_byte,
+                last_byte,
                 rtcm.message_type.map_or(String::new(), |m| m.to_string()),
                 rtcm.data_length.to_string(),
                 rtcm.data_hex,
             ];
             writer.write_record(record)?;
         }
-        LogData::SensorData(sensor) => {
-            let record = vec![
+        LogData::SensorData(record) => {
+            if arduino_schema.as_deref() != Some(record.schema.as_ref()) {
+                let mut header = vec![
+                    timestamp.to_string(),
+                    "ARDUINO_FIELDS".to_string(),
+                    String::new(),
+                    String::new(),
+                ];
+                header.extend(record.schema.fields.iter().map(|field| field.to_string()));
+                writer.write_record(header)?;
+                *arduino_schema = Some(Arc::clone(&record.schema));
+            }
+            let mut row = vec![
                 timestamp.to_string(),
                 "ARDUINO".to_string(),
-                sensor.motor_1_rpm.to_string(),
-                sensor.motor_2_rpm.to_string(),
-                sensor.motor_1_tot_rotations.to_string(),
-                sensor.motor_2_tot_rotations.to_string(),
-                sensor.time_ms.to_string(),
-                sensor.current_motor_1_ma.to_string(),
-                sensor.current_motor_2_ma.to_string(),
-                sensor.sonar_mm.to_string(),
-                sensor.tof1_mm.to_string(),
-                sensor.tof2_mm.to_string(),
+                first_byte,
+                last_byte,
+            ];
+            row.extend(record.values.iter().map(|value| value.to_string()));
+            writer.write_record(row)?;
+        }
+        LogData::BaseStation(base) => {
+            let record = vec![
+                timestamp.to_string(),
+                "BASE".to_string(),
+                first_byte,
+                last_byte,
+                base.station_id.to_string(),
+                base.ecef_x.to_string(),
+                base.ecef_y.to_string(),
+                base.ecef_z.to_string(),
+                base.antenna_height_m.map_or(String::new(), |h| h.to_string()),
+                base.baseline_m.map_or(String::new(), |b| b.to_string()),
+                base.base_satellites,
+            ];
+            writer.write_record(record)?;
+        }
+        LogData::Ubx(ubx) => {
+            let optional = |value: Option<f64>| value.map_or(String::new(), |v| v.to_string());
+            let record = vec![
+                timestamp.to_string(),
+                "UBX".to_string(),
+                first_byte,
+                last_byte,
+                ubx.ubx_message,
+                ubx.itow_ms.to_string(),
+                optional(ubx.ubx_latitude),
+                optional(ubx.ubx_longitude),
+                optional(ubx.height_m),
+                optional(ubx.h_acc_m),
+                optional(ubx.v_acc_m),
+                ubx.carrier_solution.unwrap_or_default(),
+                ubx.num_sv.map_or(String::new(), |n| n.to_string()),
+                optional(ubx.rel_north_m),
+                optional(ubx.rel_east_m),
+                optional(ubx.rel_down_m),
+            ];
+            writer.write_record(record)?;
+        }
+        LogData::MotorControl(control) => {
+            let record = vec![
+                timestamp.to_string(),
+                "MOTOR_CONTROL".to_string(),
+                first_byte,
+                last_byte,
+                control.motor.to_string(),
+                control.setpoint_rpm.to_string(),
+                control.measured_rpm.to_string(),
+                control.output_percent.to_string(),
+                control.p_term.to_string(),
+                control.i_term.to_string(),
+                control.d_term.to_string(),
+                control.ff_term.to_string(),
+                control.saturated.to_string(),
+            ];
+            writer.write_record(record)?;
+        }
+        LogData::Event(event) => {
+            let record = vec![
+                timestamp.to_string(),
+                "EVENT".to_string(),
+                first_byte,
+                last_byte,
+                event.source,
+                event.message,
             ];
             writer.write_record(record)?;
         }
@@ -249,22 +462,15 @@ fn get_timestamp_nanos() -> u64 {
         .as_nanos() as u64
 }
 
-/// Extract RTCM message type from data
-/// RTCM v3 format: first 12 bits after preamble contain message type
-fn extract_rtcm_message_type(data: &[u8]) -> Option<u16> {
-    if data.len() < 3 {
-        return None;
+/// The log columns for an RTCM3 frame. The message type is the first 12
+/// bits of the payload, not of the header.
+fn rtcm_data(frame: &RtcmFrame) -> RtcmData {
+    let data = frame.as_bytes();
+    RtcmData {
+        message_type: frame.message_type(),
+        data_length: data.len(),
+        data_hex: hex_encode(data),
     }
-
-    // RTCM v3 starts with 0xD3 preamble
-    if data[0] != 0xD3 {
-        return None;
-    }
-
-    // Message type is in bits 12-23 of the header
-    // Byte 1 bits 0-5 and byte 2 bits 0-5 contain the message type
-    let msg_type = ((data[1] as u16 & 0x3F) << 6) | ((data[2] as u16) >> 2);
-    Some(msg_type)
 }
 
 /// Convert bytes to hex string
@@ -278,13 +484,20 @@ fn hex_encode(data: &[u8]) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rtcm::framer::{self, RtcmParse};
 
     #[test]
     fn test_rtcm_message_type_extraction() {
-        // RTCM message 1005 example
-        let data = vec![0xD3, 0x00, 0x13, 0x3E, 0xD0]; // Simplified
-        let msg_type = extract_rtcm_message_type(&data);
-        assert!(msg_type.is_some());
+        // RTCM message 1005 with a 19 byte payload
+        let mut payload = vec![0x3E, 0xD0];
+        payload.resize(19, 0);
+        let data = framer::build_frame(&payload);
+        let RtcmParse::Complete(frame) = framer::parse_frame(&data) else {
+            panic!("not a valid frame");
+        };
+        let logged = rtcm_data(&frame);
+        assert_eq!(logged.message_type, Some(1005));
+        assert_eq!(logged.data_length, 25);
     }
 
     #[test]
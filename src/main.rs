@@ -2,116 +2,1011 @@
 use clap::Parser;
 use crossterm::execute;
 use std::io::{Write, stdout};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
 use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
 
+use maarco::ntrip::caster::CasterConfig;
+use maarco::ntrip::{NtripConfig, NtripVersion};
+use maarco::rtcm::messages::{BaseStation, RtcmMessage};
+use maarco::rtcm::monitor::CorrectionMonitor;
+use nmea::Nmea;
+use maarco::discovery::{self, DeviceRole, DevicesConfig, DiscoveredPort};
+use maarco::gps::demux::GpsFrame;
+use maarco::gps::fix::FixTracker;
+use maarco::gps::satellites::SatelliteTracker;
+use maarco::gps::parser::{NmeaError, NmeaStats};
+use maarco::gps::receiver::{self, CommandResult, ReceiverConfig, ReceiverProtocol};
+use maarco::local_base::LocalBaseConfig;
+use maarco::motor::pid::{MotorControlConfig, RpmController};
+use maarco::motor::{self, CommandOutcome, MotorCommand};
+use maarco::serial_link::{self, SerialLink};
+use maarco::telemetry::TelemetryStats;
+use maarco::usb_serial::{ArduinoError, ArduinoProtocol};
+use maarco::{config, display, gps, gps_serial, local_base, logging, ntrip, rtcm, usb_serial};
 
-mod display;
-mod gps;
-mod ntrip;
-mod gps_serial;
-mod logging;
-mod usb_serial;
 
+/// Ports to fall back to when discovery doesn't find the devices
+const GPS_PORT: &str = "/dev/ttyUSB0";
+const ARDUINO_PORT: &str = "/dev/ttyACM0";
+/// How often to log NMEA and Arduino error statistics
+const NMEA_STATS_INTERVAL: Duration = Duration::from_secs(60);
+/// How long to wait between loops when no device is connected
+const IDLE_INTERVAL: Duration = Duration::from_millis(50);
+/// How often to redraw the display
+const DISPLAY_INTERVAL: Duration = Duration::from_millis(200);
+/// How often to check the config file for new motor gains
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// How long to wait on the way out for the Arduino to confirm the motors stopped
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// TOML config file (command line options override its values)
+    #[arg(long)]
+    config: Option<PathBuf>,
     /// NTRIP mountpoint (e.g., MOUNTPOINT)
     #[arg(long)]
     ntrip_mount: Option<String>,  // E.g. "VMAX-LAND-1"
-    /// GPS serial port path (e.g., /dev/ttyUSB0)
-    #[arg(long, default_value = "/dev/ttyUSB0")]
-    gps_port: PathBuf,
-    /// Arduino serial port path (e.g., /dev/ttyACM0)
-    #[arg(long, default_value = "/dev/ttyACM0")]
-    arduino_port: PathBuf,
+    /// NTRIP caster host (default: rtk2go.com)
+    #[arg(long)]
+    ntrip_host: Option<String>,
+    /// NTRIP caster port (default: 2101)
+    #[arg(long)]
+    ntrip_port: Option<u16>,
+    /// NTRIP username (RTK2GO expects an email address)
+    #[arg(long)]
+    ntrip_user: Option<String>,
+    /// NTRIP password (default: none)
+    #[arg(long)]
+    ntrip_password: Option<String>,
+    /// Connect to the NTRIP caster over TLS
+    #[arg(long)]
+    ntrip_tls: bool,
+    /// NTRIP protocol version (default: auto, which tries v2 and falls back to v1)
+    #[arg(long, value_enum)]
+    ntrip_version: Option<NtripVersion>,
+    /// Pick the nearest RTCM3 mountpoint from the caster's sourcetable once we have a fix
+    #[arg(long)]
+    ntrip_auto_mount: bool,
+    /// Print the caster's mountpoints and exit
+    #[arg(long)]
+    ntrip_list_mounts: bool,
+    /// Send our GGA position to the caster every N seconds (needed for VRS/network mountpoints)
+    #[arg(long)]
+    ntrip_gga_interval: Option<u64>,
+    /// Read RTCM3 corrections from a local base receiver on this serial port instead of NTRIP
+    #[arg(long)]
+    base_port: Option<PathBuf>,
+    /// Baud rate of the local base receiver (default: 115200)
+    #[arg(long)]
+    base_baud: Option<u32>,
+    /// Read RTCM3 corrections from a raw TCP stream (host:port) instead of NTRIP
+    #[arg(long)]
+    base_tcp: Option<String>,
+    /// Re-serve the corrections we receive to other rovers as an NTRIP caster
+    #[arg(long)]
+    caster: bool,
+    /// Port for the built-in caster (default: 2101)
+    #[arg(long)]
+    caster_port: Option<u16>,
+    /// Mountpoint name for the built-in caster (default: MAARCO)
+    #[arg(long)]
+    caster_mount: Option<String>,
+    /// Username rovers must use with the built-in caster (default: no login)
+    #[arg(long)]
+    caster_user: Option<String>,
+    /// Password rovers must use with the built-in caster
+    #[arg(long)]
+    caster_password: Option<String>,
+    /// Warn when corrections are older than this many seconds
+    #[arg(long, default_value_t = 10)]
+    correction_timeout: u64,
+    /// Apply this receiver profile (e.g. boat or survey) to the GPS at startup
+    #[arg(long)]
+    receiver_profile: Option<String>,
+    /// Command set the GPS receiver understands (default: pair)
+    #[arg(long, value_enum)]
+    receiver_protocol: Option<ReceiverProtocol>,
+    /// GPS serial port path (default: found by USB ID, else /dev/ttyUSB0)
+    #[arg(long)]
+    gps_port: Option<PathBuf>,
+    /// GPS baud rate (default: detected automatically)
+    #[arg(long)]
+    gps_baud: Option<u32>,
+    /// Arduino serial port path (default: found by USB ID, else /dev/ttyACM0)
+    #[arg(long)]
+    arduino_port: Option<PathBuf>,
+    /// Arduino baud rate (default: detected automatically)
+    #[arg(long)]
+    arduino_baud: Option<u32>,
+    /// How the Arduino firmware sends its data (default: text)
+    #[arg(long, value_enum, default_value_t)]
+    arduino_protocol: ArduinoProtocol,
+    /// Hold motor 1 at this RPM (needs --arduino-protocol binary)
+    #[arg(long)]
+    motor_1_rpm: Option<f64>,
+    /// Hold motor 2 at this RPM (needs --arduino-protocol binary)
+    #[arg(long)]
+    motor_2_rpm: Option<f64>,
+    /// Print the serial devices found and the role each would get, then exit
+    #[arg(long)]
+    list_ports: bool,
     /// Log file path (default: bot_test.csv)
     #[arg(long, default_value = "bot_test.csv")]
     log_file: PathBuf,
 }
 
+/// Merge the NTRIP settings from the config file with the command line.
+fn ntrip_config(args: &Args, file_config: Option<NtripConfig>) -> NtripConfig {
+    let mut config = file_config.unwrap_or_default();
+    if let Some(mount) = &args.ntrip_mount {
+        config.mountpoint = mount.clone();
+    }
+    if let Some(host) = &args.ntrip_host {
+        config.host = host.clone();
+    }
+    if let Some(port) = args.ntrip_port {
+        config.port = port;
+    }
+    if let Some(user) = &args.ntrip_user {
+        config.username = user.clone();
+    }
+    if let Some(password) = &args.ntrip_password {
+        config.password = password.clone();
+    }
+    if args.ntrip_tls {
+        config.tls = true;
+    }
+    if let Some(version) = args.ntrip_version {
+        config.version = version;
+    }
+    if args.ntrip_auto_mount {
+        config.auto_mountpoint = true;
+    }
+    if let Some(interval) = args.ntrip_gga_interval {
+        config.gga_interval_secs = interval;
+    }
+    config
+}
+
+/// Merge the built-in caster settings from the config file with the command
+/// line. Returns `None` if the caster is disabled.
+fn caster_config(args: &Args, file_config: Option<CasterConfig>) -> Option<CasterConfig> {
+    if file_config.is_none() && !args.caster {
+        return None;
+    }
+    let mut config = file_config.unwrap_or_default();
+    if let Some(port) = args.caster_port {
+        config.port = port;
+    }
+    if let Some(mount) = &args.caster_mount {
+        config.mountpoint = mount.clone();
+    }
+    if let Some(user) = &args.caster_user {
+        config.username = Some(user.clone());
+    }
+    if let Some(password) = &args.caster_password {
+        config.password = Some(password.clone());
+    }
+    Some(config)
+}
+
+/// Merge the local base settings from the config file with the command line.
+fn local_base_config(args: &Args, file_config: Option<LocalBaseConfig>) -> LocalBaseConfig {
+    let mut config = file_config.unwrap_or_default();
+    if let Some(port) = &args.base_port {
+        config.serial_port = Some(port.clone());
+    }
+    if let Some(baud) = args.base_baud {
+        config.baud_rate = baud;
+    }
+    if let Some(address) = &args.base_tcp {
+        config.tcp_address = Some(address.clone());
+    }
+    config
+}
+
+/// Merge the receiver settings from the config file with the command line.
+fn receiver_config(args: &Args, file_config: Option<ReceiverConfig>) -> ReceiverConfig {
+    let mut config = file_config.unwrap_or_default();
+    if let Some(profile) = &args.receiver_profile {
+        config.profile = Some(profile.clone());
+    }
+    if let Some(protocol) = args.receiver_protocol {
+        config.protocol = protocol;
+    }
+    config
+}
+
+/// Merge the motor control settings from the config file with the command
+/// line.
+fn motor_control_config(
+    args: &Args,
+    file_config: Option<MotorControlConfig>,
+) -> MotorControlConfig {
+    let mut config = file_config.unwrap_or_default();
+    if let Some(rpm) = args.motor_1_rpm {
+        config.motor_1_rpm = Some(rpm);
+    }
+    if let Some(rpm) = args.motor_2_rpm {
+        config.motor_2_rpm = Some(rpm);
+    }
+    config
+}
+
+/// When the config file was last changed, to notice edited motor gains
+fn config_modified(args: &Args) -> Option<SystemTime> {
+    let path = args.config.as_ref()?;
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn rpm_control_summary(config: &MotorControlConfig) -> String {
+    let setpoint = |rpm: Option<f64>| rpm.map_or("off".to_string(), |rpm| format!("{} RPM", rpm));
+    let gains = config.gains;
+    format!(
+        "RPM control: motor 1 {}, motor 2 {}, kp {} ki {} kd {} kf {}, limit {}%",
+        setpoint(config.motor_1_rpm),
+        setpoint(config.motor_2_rpm),
+        gains.kp,
+        gains.ki,
+        gains.kd,
+        gains.kf,
+        gains.output_limit
+    )
+}
+
+/// Start the RPM control loops, if any motor has a setpoint. Commands need
+/// the binary protocol.
+fn start_rpm_control(
+    logger: &logging::Logger,
+    args: &Args,
+    config: &MotorControlConfig,
+) -> Option<RpmController> {
+    if !config.is_enabled() {
+        return None;
+    }
+    if args.arduino_protocol != ArduinoProtocol::Binary {
+        let message = "RPM control needs --arduino-protocol binary";
+        eprintln!("{}", message);
+        logger.log_event("MOTOR", message);
+        return None;
+    }
+    if config.gains.is_zero() {
+        logger.log_event("MOTOR", "RPM control gains are all zero");
+    }
+    logger.log_event("MOTOR", &rpm_control_summary(config));
+    Some(RpmController::new(config))
+}
+
+/// Apply motor control settings changed while running. Motors that lost
+/// their setpoint are stopped.
+fn retune_rpm_control(
+    logger: &logging::Logger,
+    args: &Args,
+    controller: &mut Option<RpmController>,
+    config: &MotorControlConfig,
+    port: Option<&mut usb_serial::ArduinoSerialPort>,
+) {
+    let rpm_controller = match controller {
+        Some(rpm_controller) => rpm_controller,
+        None => {
+            *controller = start_rpm_control(logger, args, config);
+            return;
+        }
+    };
+    let before = rpm_controller.motors();
+    rpm_controller.retune(config);
+    logger.log_event("MOTOR", &rpm_control_summary(config));
+    let Some(port) = port else {
+        return;
+    };
+    for motor in before {
+        if rpm_controller.motors().contains(&motor) {
+            continue;
+        }
+        let stop = MotorCommand::SetPower {
+            motor,
+            percent: 0.0,
+        };
+        if let Err(e) = port.send_command(stop) {
+            logger.log_event("MOTOR", &format!("Failed to stop motor {}: {}", motor, e));
+        }
+    }
+}
+
+/// Stop the motors before exiting, waiting a moment for the Arduino to
+/// confirm it so the command isn't lost with the process
+fn stop_motors_on_exit(logger: &logging::Logger, port: &mut usb_serial::ArduinoSerialPort) {
+    if port.protocol() != ArduinoProtocol::Binary {
+        return;
+    }
+    if let Err(e) = port.send_command(MotorCommand::Stop) {
+        logger.log_event("MOTOR", &format!("Failed to stop the motors: {}", e));
+        return;
+    }
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while Instant::now() < deadline {
+        // Only the answer to the stop matters now
+        let _ = port.read_record();
+        match port.poll_commands() {
+            Ok(outcomes) => {
+                for outcome in outcomes {
+                    logger.log_event("MOTOR", &outcome.message());
+                    if outcome.command == MotorCommand::Stop {
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                logger.log_event("MOTOR", &format!("Failed to stop the motors: {}", e));
+                return;
+            }
+        }
+    }
+}
+
+/// Put the GPS receiver into the configured profile, logging any setting it
+/// didn't accept
+fn configure_receiver(
+    logger: &logging::Logger,
+    port: &mut gps_serial::SerialReader,
+    config: &ReceiverConfig,
+) -> std::io::Result<()> {
+    let Some(name) = &config.profile else {
+        return Ok(());
+    };
+    let Some(profile) = config.find_profile(name) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unknown receiver profile \"{}\"", name),
+        ));
+    };
+
+    let results = receiver::apply_profile(port, config.protocol, &profile)?;
+    let failed = results
+        .iter()
+        .filter(|(_, result)| *result != CommandResult::Ack)
+        .count();
+    for (command, result) in &results {
+        if *result != CommandResult::Ack {
+            logger.log_event("RECEIVER", &format!("{}: {:?}", command.description, result));
+        }
+    }
+    let message = format!(
+        "Applied profile {} ({} of {} settings accepted)",
+        name,
+        results.len() - failed,
+        results.len()
+    );
+    println!("{}", message);
+    logger.log_event("RECEIVER", &message);
+    Ok(())
+}
+
+/// Check the Arduino firmware answers motor commands. Text firmware can't,
+/// so there's nothing to check.
+fn ping_arduino(logger: &logging::Logger, port: &mut usb_serial::ArduinoSerialPort) {
+    if port.protocol() != ArduinoProtocol::Binary {
+        return;
+    }
+    if let Err(e) = port.send_command(MotorCommand::Ping) {
+        logger.log_event("MOTOR", &format!("Failed to send ping: {}", e));
+    }
+}
+
+/// Print the serial devices and which one we'd use for what
+fn list_ports(devices: &DevicesConfig, reserved: &[PathBuf]) -> std::io::Result<()> {
+    let ports = discovery::discover(devices, reserved, true)?;
+    if ports.is_empty() {
+        println!("No serial ports found");
+    }
+    for port in &ports {
+        let role = match port.role {
+            Some(role) => format!("{} ({})", role, port.reason),
+            None if !port.reason.is_empty() => format!("- ({})", port.reason),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:<16} {:<20} {}",
+            port.path.to_string_lossy(),
+            role,
+            port.description()
+        );
+    }
+    Ok(())
+}
+
+/// Run discovery unless every port was given on the command line. Unless
+/// `probe` is set, devices without a known USB ID aren't opened to check what
+/// they are. The `reserved` ports are left alone.
+fn discover_ports(
+    paths: &[&Option<PathBuf>],
+    reserved: &[PathBuf],
+    devices: &DevicesConfig,
+    probe: bool,
+) -> Vec<DiscoveredPort> {
+    if paths.iter().all(|path| path.is_some()) {
+        return Vec::new();
+    }
+    discovery::discover(devices, reserved, probe).unwrap_or_default()
+}
+
+/// The port to use for `role`: the one given on the command line, else the
+/// one discovery found. Also returns the baud rate if the device was probed.
+fn device_path(
+    path: &Option<PathBuf>,
+    ports: &[DiscoveredPort],
+    role: DeviceRole,
+) -> Option<(PathBuf, Option<u32>)> {
+    if let Some(path) = path {
+        return Some((path.clone(), None));
+    }
+    let found = discovery::find(ports, role)?;
+    println!("Found {} on {}", role, found.path.to_string_lossy());
+    Some((found.path.clone(), found.baud_rate))
+}
+
+/// Print the mountpoints offered by the configured caster
+fn list_mountpoints(config: &NtripConfig) -> std::io::Result<()> {
+    let table = ntrip::fetch_sourcetable(config)?;
+    println!(
+        "{:<20} {:<10} {:>9} {:>10}  {:<4} Systems",
+        "Mountpoint", "Format", "Latitude", "Longitude", "GGA"
+    );
+    for stream in &table.streams {
+        println!(
+            "{:<20} {:<10} {:>9.4} {:>10.4}  {:<4} {}",
+            stream.mountpoint,
+            stream.format,
+            stream.latitude,
+            stream.longitude,
+            if stream.nmea { "yes" } else { "no" },
+            stream.nav_system
+        );
+    }
+    Ok(())
+}
+
+/// Distance from the rover's current fix to the base station antenna
+fn rover_baseline(base: &BaseStation, parser: &Nmea) -> Option<f64> {
+    let latitude = parser.latitude?;
+    let longitude = parser.longitude?;
+    // RTCM positions are ellipsoidal, GGA altitude is above mean sea level
+    let height = parser.altitude? + parser.geoid_separation.unwrap_or(0.0);
+    base.baseline_m(latitude, longitude, height as f64)
+}
+
+/// Update what we know about the base station from a decoded RTCM message
+fn record_base_station(
+    logger: &logging::Logger,
+    base: &mut BaseStation,
+    message: &RtcmMessage,
+    parser: &Nmea,
+) {
+    let previous_descriptor = base.descriptor.clone();
+    base.update(message);
+
+    match message {
+        RtcmMessage::StationPosition(_) => {
+            logger.log_base_station(base, rover_baseline(base, parser));
+        }
+        RtcmMessage::ReceiverDescriptor(descriptor)
+            if previous_descriptor.as_ref() != Some(descriptor) =>
+        {
+            logger.log_event(
+                "RTCM",
+                &format!(
+                    "Base receiver {} ({}), antenna {}",
+                    descriptor.receiver, descriptor.firmware, descriptor.antenna
+                ),
+            );
+        }
+        _ => {}
+    }
+}
+
 fn main() -> std::io::Result<()> {
     let args = Args::parse();
+    let file_config = match &args.config {
+        Some(path) => config::load(path)?,
+        None => config::Config::default(),
+    };
+    let ntrip_config = ntrip_config(&args, file_config.ntrip);
+    let caster_config = caster_config(&args, file_config.caster);
+    let local_base_config = local_base_config(&args, file_config.local_base);
+    let receiver_config = receiver_config(&args, file_config.receiver);
+    let motor_control = motor_control_config(&args, file_config.motor_control);
+    let devices = file_config.devices.unwrap_or_default();
+    // Ports discovery mustn't hand out: the local base's, and the ones given
+    // on the command line
+    let reserved: Vec<PathBuf> =
+        [&local_base_config.serial_port, &args.gps_port, &args.arduino_port]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+    if args.ntrip_list_mounts {
+        return list_mountpoints(&ntrip_config);
+    }
+    if args.list_ports {
+        return list_ports(&devices, &reserved);
+    }
 
-    let logger = logging::Logger::new(args.log_file)?;
-    let mut gps_port = gps_serial::open_port(args.gps_port);
-    let mut arduino_port = usb_serial::open_port(args.arduino_port);
+    let logger = logging::Logger::new(args.log_file.clone())?;
+    // Probing listens to each device for a while, so it's only done once
+    let ports =
+        discover_ports(&[&args.gps_port, &args.arduino_port], &reserved, &devices, true);
+    let (gps_path, probed_gps_baud) = device_path(&args.gps_port, &ports, DeviceRole::Gps)
+        .unwrap_or_else(|| (PathBuf::from(GPS_PORT), None));
+    let (arduino_path, probed_arduino_baud) =
+        device_path(&args.arduino_port, &ports, DeviceRole::Arduino)
+            .unwrap_or_else(|| (PathBuf::from(ARDUINO_PORT), None));
+    // A rate the device was already heard at doesn't need detecting again
+    let gps_baud = args.gps_baud.or(probed_gps_baud);
+    let arduino_baud = args.arduino_baud.or(probed_arduino_baud);
+    let mut gps_port = gps_serial::open_port(gps_path.clone(), gps_baud);
+    let mut arduino_port =
+        usb_serial::open_port(arduino_path.clone(), arduino_baud, args.arduino_protocol);
 
-    let gps_connected = gps_port.is_ok();
-    let arduino_connected = arduino_port.is_ok();
+    if let Ok(port) = gps_port.as_mut() {
+        configure_receiver(&logger, port, &receiver_config)?;
+    }
+    if let Ok(port) = arduino_port.as_mut() {
+        ping_arduino(&logger, port);
+    }
 
-    if !gps_connected && !arduino_connected {
+    // A base station node only runs the caster, so it needs no devices
+    if gps_port.is_err() && arduino_port.is_err() && caster_config.is_none() {
         eprintln!("No serial ports connected. Exiting.");
         return Ok(());
     }
 
+    // Reopen at the rate we found at startup rather than detecting it again
+    let mut gps_baud = gps_port.as_ref().map_or(gps_baud, |port| Some(port.baud_rate()));
+    let mut arduino_baud = arduino_port
+        .as_ref()
+        .map_or(arduino_baud, |port| Some(port.baud_rate()));
+    let mut gps_link = SerialLink::new(gps_port.ok());
+    let mut arduino_link = SerialLink::new(arduino_port.ok());
+
     let mut parser = gps::parser::build_parser();
+    let mut nmea_stats = NmeaStats::new();
+    let mut fix_tracker = FixTracker::new(Instant::now());
+    let mut satellites = SatelliteTracker::new();
+    let mut last_stats_log = Instant::now();
+    let mut last_logged_errors = 0;
+    let mut telemetry_stats = TelemetryStats::default();
+    let mut last_logged_telemetry_errors = 0;
+    let mut last_logged_dropped_frames = 0;
+    let mut last_logged_unknown_bytes = 0;
+    let mut rpm_controller = start_rpm_control(&logger, &args, &motor_control);
+    let mut last_command: Option<CommandOutcome> = None;
+    let mut last_failed_command: Option<CommandOutcome> = None;
+    let mut config_modified_at = config_modified(&args);
+    let mut last_config_check = Instant::now();
     let mut stdout = stdout();
     let mut display = display::Display::new();
+    let mut last_draw = Instant::now();
 
     // Initialize terminal
     execute!(stdout, crossterm::cursor::SetCursorStyle::BlinkingBlock)?;
 
     // Channel for NTRIP data to write to serial
     let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    // Corrections arrive in arbitrary chunks, split them back into RTCM frames
+    let mut rtcm_framer = rtcm::framer::RtcmFramer::new();
+    let mut base_station = rtcm::messages::BaseStation::new();
+
+    // Optionally pass the corrections on to other rovers
+    let caster = match caster_config {
+        Some(config) => Some(ntrip::caster::start_caster(config)?),
+        None => None,
+    };
 
-    // Start NTRIP thread if configured
-    if let Some(mount) = args.ntrip_mount {
+    // Start the local base or NTRIP thread if configured
+    let ntrip_status = ntrip::NtripStatusHandle::new();
+    let rover_position = ntrip::RoverPosition::new();
+    let mut last_ntrip_state = None;
+    let mut corrections = None;
+    if local_base_config.is_configured()
+        || !ntrip_config.mountpoint.is_empty()
+        || ntrip_config.auto_mountpoint
+    {
+        let timeout = Duration::from_secs(args.correction_timeout);
+        corrections = Some(CorrectionMonitor::new(timeout, Instant::now()));
+    }
+    if local_base_config.is_configured() {
+        if !ntrip_config.mountpoint.is_empty() {
+            println!("Local base configured, not connecting to NTRIP");
+        }
+        std::thread::spawn(move || {
+            local_base::run_local_base(tx, local_base_config);
+        });
+        println!("Started local base thread");
+    } else if !ntrip_config.mountpoint.is_empty() || ntrip_config.auto_mountpoint {
+        let status = ntrip_status.clone();
+        let position = rover_position.clone();
         std::thread::spawn(move || {
-            ntrip::connect_rtk2go_ntrip(tx, &mount);
+            ntrip::run_ntrip_client(tx, ntrip_config, status, position);
         });
         println!("Started NTRIP thread");
+        last_ntrip_state = Some(ntrip_status.get().state);
     }
 
-    loop {
+    // Ctrl-C ends the loop, so the motors can be stopped on the way out
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = running.clone();
+    ctrlc::set_handler(move || handler_running.store(false, Ordering::SeqCst))
+        .map_err(std::io::Error::other)?;
+
+    while running.load(Ordering::SeqCst) {
+        // Record NTRIP connection state changes
+        if let Some(last_state) = last_ntrip_state {
+            let status = ntrip_status.get();
+            if status.state != last_state {
+                let message = match (&status.state, &status.last_error) {
+                    (ntrip::NtripState::BackingOff, Some(error)) => {
+                        format!("{:?} ({})", status.state, error)
+                    }
+                    (ntrip::NtripState::Authenticating, _) => format!(
+                        "{:?} ({})",
+                        status.state,
+                        status.mountpoint.as_deref().unwrap_or_default()
+                    ),
+                    _ => format!("{:?}", status.state),
+                };
+                logger.log_event("NTRIP", &message);
+                last_ntrip_state = Some(status.state);
+            }
+        }
+
+        // Warn when corrections stop arriving
+        if let Some(monitor) = corrections.as_mut()
+            && let Some(stale) = monitor.update(Instant::now())
+        {
+            let message = if stale {
+                format!("Corrections stale: {}", monitor.summary(Instant::now()))
+            } else {
+                format!("Corrections restored: {}", monitor.summary(Instant::now()))
+            };
+            // The corrections panel shows the warning while they're stale
+            logger.log_event("CORRECTIONS", &message);
+        }
+
+        // Summarize NMEA errors now and then, if there were any
+        if last_stats_log.elapsed() >= NMEA_STATS_INTERVAL {
+            if nmea_stats.total_errors() > last_logged_errors {
+                last_logged_errors = nmea_stats.total_errors();
+                logger.log_event("NMEA", &format!("Sentence stats: {}", nmea_stats.summary()));
+            }
+            if let Some(port) = gps_link.get()
+                && port.unknown_bytes() > last_logged_unknown_bytes
+            {
+                last_logged_unknown_bytes = port.unknown_bytes();
+                logger.log_event(
+                    "GPS",
+                    &format!("{} bytes not part of any frame", port.unknown_bytes()),
+                );
+            }
+            if telemetry_stats.errors() > last_logged_telemetry_errors {
+                last_logged_telemetry_errors = telemetry_stats.errors();
+                logger.log_event(
+                    "ARDUINO",
+                    &format!("Telemetry stats: {}", telemetry_stats.summary()),
+                );
+            }
+            if let Some(port) = arduino_link.get()
+                && port.dropped_frames() > last_logged_dropped_frames
+            {
+                last_logged_dropped_frames = port.dropped_frames();
+                logger.log_event(
+                    "ARDUINO",
+                    &format!("{} binary frames dropped", port.dropped_frames()),
+                );
+            }
+            last_stats_log = Instant::now();
+        }
+
+        // Pick up motor gains and setpoints edited in the config file
+        if last_config_check.elapsed() >= CONFIG_CHECK_INTERVAL {
+            last_config_check = Instant::now();
+            let modified = config_modified(&args);
+            if modified != config_modified_at {
+                config_modified_at = modified;
+                match args.config.as_deref().map(config::load) {
+                    Some(Ok(file_config)) => {
+                        let config = motor_control_config(&args, file_config.motor_control);
+                        retune_rpm_control(
+                            &logger,
+                            &args,
+                            &mut rpm_controller,
+                            &config,
+                            arduino_link.get_mut(),
+                        );
+                    }
+                    Some(Err(e)) => logger.log_event("CONFIG", &format!("Not reloaded: {}", e)),
+                    None => {}
+                }
+            }
+        }
+
+        // Reopen serial devices that went away
+        // The device may come back under a different name
+        if let Some(port) = gps_link.reopen(|| {
+            let ports = discover_ports(&[&args.gps_port], &reserved, &devices, false);
+            let path = device_path(&args.gps_port, &ports, DeviceRole::Gps)
+                .map_or_else(|| gps_path.clone(), |(path, _)| path);
+            gps_serial::open_port(path, gps_baud)
+        }) {
+            gps_baud = Some(port.baud_rate());
+            // The new port counts unknown bytes from zero
+            last_logged_unknown_bytes = 0;
+            logger.log_event("GPS", &format!("Reconnected at {} baud", port.baud_rate()));
+            if let Err(e) = configure_receiver(&logger, port, &receiver_config) {
+                logger.log_event("GPS", &format!("Failed to configure receiver: {}", e));
+            }
+        }
+        if let Some(port) = arduino_link.reopen(|| {
+            let ports = discover_ports(&[&args.arduino_port], &reserved, &devices, false);
+            let path = device_path(&args.arduino_port, &ports, DeviceRole::Arduino)
+                .map_or_else(|| arduino_path.clone(), |(path, _)| path);
+            usb_serial::open_port(path, arduino_baud, args.arduino_protocol)
+        }) {
+            arduino_baud = Some(port.baud_rate());
+            // The new port counts dropped frames from zero
+            last_logged_dropped_frames = 0;
+            logger.log_event("ARDUINO", &format!("Reconnected at {} baud", port.baud_rate()));
+            ping_arduino(&logger, port);
+            // Opening the port reset the Arduino, and the motors with it
+            if let Some(controller) = rpm_controller.as_mut() {
+                controller.reset();
+            }
+        }
 
         // Read from GPS serial port, if connected
-        if gps_connected {
-            let gps_serial_data = gps_port.as_mut().unwrap().read_sentences();
-            if gps_serial_data.is_err() {
-                continue;
-            }
-            let sentences = gps_serial_data.unwrap();
-            for sentence in &sentences {
-                gps::parser::parse_nmea_sentence(&mut parser, sentence);
-                logger.log_nmea(sentence);
-            }
-
-            // Write any pending NTRIP correction data to serial
-            loop {
-                match rx.try_recv() {
-                    Ok(data) => {
-                        // println!("Writing {} bytes of NTRIP data to serial", data.len());
+        if let Some(gps_port) = gps_link.get_mut() {
+            let frames = match gps_port.read_frames() {
+                Ok(frames) => frames,
+                Err(e) if serial_link::is_disconnect(&e) => {
+                    logger.log_event("GPS", &format!("Disconnected: {}", e));
+                    gps_link.disconnect();
+                    continue;
+                }
+                // No data yet
+                Err(_) => Vec::new(),
+            };
+            let mut gga_fix = None;
+            for frame in &frames {
+                let arrival = Some(frame.arrival);
+                match &frame.value {
+                    GpsFrame::Nmea(sentence) => {
+                        let result = gps::parser::parse_nmea_sentence(&mut parser, sentence);
+                        nmea_stats.record(sentence, &result);
+                        if matches!(result, Ok(()) | Err(NmeaError::Unsupported)) {
+                            satellites.record(sentence, frame.arrival.last_byte);
+                        }
+                        if result.is_ok() && sentence.get(3..6) == Some("GGA") {
+                            let fix_type = gps::parser::gga_fix_type(sentence);
+                            gga_fix = Some((fix_type, frame.arrival.last_byte));
+                        }
+                        match result {
+                            Ok(()) | Err(NmeaError::Unsupported) => {
+                                logger.log_nmea(sentence, arrival)
+                            }
+                            Err(e) => {
+                                logger.log_event("NMEA", &format!("{}: {}", e, sentence.trim()))
+                            }
+                        }
+                    }
+                    GpsFrame::Ubx(frame) => {
+                        if let Some(message) = gps::ubx::decode(frame) {
+                            logger.log_ubx(&message, arrival);
+                        }
+                    }
+                    // RTCM the receiver itself outputs, e.g. when set up as a base
+                    GpsFrame::Rtcm(frame) => logger.log_rtcm(frame, arrival),
+                }
+            }
+            // Time the transition by when the GGA came in, not when we got to it
+            if let Some((fix_type, read_at)) = gga_fix
+                && let Some(transition) = fix_tracker.update(fix_type, read_at)
+            {
+                // A degraded fix shows as an alert on the fix panel
+                logger.log_event("FIX", &transition.message());
+            }
+            // Only satellites that were part of the solution are worth an event
+            for dropout in satellites.update(Instant::now()) {
+                if dropout.was_used {
+                    let elevation = dropout.elevation.map_or("-".to_string(), |e| e.to_string());
+                    logger.log_event(
+                        "SATELLITES",
+                        &format!(
+                            "{} {} dropped out (elevation {})",
+                            dropout.constellation, dropout.prn, elevation
+                        ),
+                    );
+                }
+            }
+            if let (Some(latitude), Some(longitude)) = (parser.latitude, parser.longitude) {
+                rover_position.update(latitude, longitude);
+            }
+            if !frames.is_empty()
+                && let Some(gga) = gps_port.last_gga()
+            {
+                rover_position.update_gga(gga);
+                if let Some(monitor) = corrections.as_mut() {
+                    monitor.record_differential_age(gps::parser::gga_differential_age(gga));
+                }
+            }
+        }
+
+        // Frame the corrections whether or not the GPS is connected, so the
+        // caster and the correction monitor keep going without a rover
+        let mut correction_frames = Vec::new();
+        loop {
+            match rx.try_recv() {
+                Ok(data) => {
+                    let corrupt_before = rtcm_framer.corrupt_frames;
+                    for frame in rtcm_framer.push(&data) {
                         // Log RTCM data
-                        logger.log_rtcm(&data);
-                        gps_port.as_mut().unwrap().write_all(&data)?;
-                        gps_port.as_mut().unwrap().flush()?;
+                        logger.log_rtcm(&frame, None);
+                        if let Some(monitor) = corrections.as_mut() {
+                            monitor.record_frame(frame.as_bytes().len(), Instant::now());
+                        }
+                        if let Some(caster) = &caster {
+                            caster.broadcast(frame.as_bytes());
+                        }
+                        if let Some(message) = rtcm::messages::decode(&frame) {
+                            record_base_station(&logger, &mut base_station, &message, &parser);
+                        }
+                        correction_frames.push(frame);
                     }
-                    Err(TryRecvError::Empty) => break,
-                    Err(e) => {
-                        eprintln!("Channel error: {:?}", e);
-                        break;
+                    if rtcm_framer.corrupt_frames > corrupt_before {
+                        logger.log_event(
+                            "RTCM",
+                            &format!("Dropped corrupt frame ({} total)", rtcm_framer.corrupt_frames),
+                        );
                     }
                 }
+                Err(TryRecvError::Empty) => break,
+                Err(e) => {
+                    eprintln!("Channel error: {:?}", e);
+                    break;
+                }
             }
+        }
 
-            // display.update_gps(&mut stdout, &parser)?;
+        // Write the corrections to the GPS, if connected
+        if !correction_frames.is_empty()
+            && let Some(gps_port) = gps_link.get_mut()
+        {
+            let result = correction_frames
+                .iter()
+                .try_for_each(|frame| gps_port.write_all(frame.as_bytes()))
+                .and_then(|()| gps_port.flush());
+            if let Err(e) = result {
+                logger.log_event("GPS", &format!("Disconnected: {}", e));
+                gps_link.disconnect();
+            }
         }
 
-        // Read from Arduino serial port, if connected
-        if arduino_connected {
-            let arduino_serial_data = arduino_port.as_mut().unwrap().read_line();
-            if arduino_serial_data.is_err() {
-                println!("Error reading from Arduino serial port");
-                continue;
+        // Read from Arduino serial port, if connected. Errors leave this
+        // block rather than the loop, so the motor watchdog below still runs.
+        'arduino: {
+            let Some(arduino_port) = arduino_link.get_mut() else {
+                break 'arduino;
+            };
+            // Resend unanswered motor commands, and log how the rest went
+            match arduino_port.poll_commands() {
+                Ok(outcomes) => {
+                    for outcome in outcomes {
+                        logger.log_event("MOTOR", &outcome.message());
+                        if outcome.result != motor::CommandResult::Ack {
+                            last_failed_command = Some(outcome.clone());
+                        }
+                        last_command = Some(outcome);
+                    }
+                }
+                Err(e) => {
+                    let e = serialport::Error::from(e);
+                    logger.log_event("ARDUINO", &format!("Failed to send command: {}", e));
+                    if serial_link::is_disconnect(&e) {
+                        arduino_link.disconnect();
+                        break 'arduino;
+                    }
+                }
+            }
+
+            let arduino_serial_data = arduino_port.read_record();
+            let sensor_data = match arduino_serial_data {
+                Ok(sensor_data) => sensor_data,
+                Err(ArduinoError::Io(e)) if serial_link::is_disconnect(&e) => {
+                    logger.log_event("ARDUINO", &format!("Disconnected: {}", e));
+                    arduino_link.disconnect();
+                    break 'arduino;
+                }
+                // A garbled line, skip it and carry on with the next one
+                Err(ArduinoError::Telemetry { line, error }) => {
+                    logger.log_event("ARDUINO", &format!("{}: {}", error, line));
+                    telemetry_stats.record(&Err(error));
+                    None
+                }
+                Err(ArduinoError::Frame(error)) => {
+                    logger.log_event("ARDUINO", &format!("{}", error));
+                    telemetry_stats.frame_errors += 1;
+                    None
+                }
+                Err(e @ ArduinoError::UnknownMessage(_)) => {
+                    logger.log_event("ARDUINO", &format!("{}", e));
+                    None
+                }
+                Err(ArduinoError::Io(e)) => {
+                    logger.log_event("ARDUINO", &format!("Read error: {}", e));
+                    None
+                }
+            };
+            if sensor_data.is_some() {
+                telemetry_stats.record(&Ok(()));
             }
-            let sensor_data = arduino_serial_data.unwrap();
             if let Some(ref data) = sensor_data {
-                logger.log_sensor_data(data);
+                logger.log_sensor_data(&data.value, Some(data.arrival));
+                display.update_arduino(&data.value);
+            }
+            if let (Some(data), Some(controller)) = (&sensor_data, rpm_controller.as_mut()) {
+                for output in controller.update(&data.value, data.arrival.last_byte) {
+                    logger.log_motor_control(&output, Some(data.arrival));
+                    if let Err(e) = arduino_port.send_command(output.command()) {
+                        logger.log_event("MOTOR", &format!("Failed to send command: {}", e));
+                    }
+                }
             }
-            // display.update_arduino(&mut stdout, &sensor_data.unwrap())?;
         };
 
+        // Without RPM readings the loops can't hold the motors, whether the
+        // Arduino went quiet or its cable came out
+        if let Some(controller) = rpm_controller.as_mut()
+            && let Some(result) =
+                usb_serial::stop_if_readings_lost(controller, arduino_link.get_mut(), Instant::now())
+        {
+            logger.log_event("MOTOR", "RPM readings stopped, stopping the motors");
+            if let Err(e) = result {
+                logger.log_event("MOTOR", &format!("Failed to stop the motors: {}", e));
+            }
+        }
+
+        // Redraw a few times a second rather than after every sentence
+        if last_draw.elapsed() >= DISPLAY_INTERVAL {
+            last_draw = Instant::now();
+            display.update_links(&[
+                ("GPS", gps_link.state(), gps_link.reconnects),
+                ("Arduino", arduino_link.state(), arduino_link.reconnects),
+            ]);
+            display.update_gps(&parser);
+            display.update_fix(&fix_tracker);
+            display.update_satellites(&satellites);
+            let baseline = rover_baseline(&base_station, &parser);
+            display.update_base_station(&base_station, baseline);
+            if let Some(monitor) = &corrections {
+                display.update_corrections(monitor);
+            }
+            if let Some(outcome) = &last_command {
+                display.update_motors(outcome, last_failed_command.as_ref());
+            }
+            display.draw(&mut stdout)?;
+        }
+
+        // Without a serial read to wait on, the loop would spin
+        if gps_link.get().is_none() && arduino_link.get().is_none() {
+            std::thread::sleep(IDLE_INTERVAL);
+        }
+    }
+
+    if let Some(port) = arduino_link.get_mut() {
+        stop_motors_on_exit(&logger, port);
     }
+    Ok(())
 }
@@ -0,0 +1,340 @@
+// src/motor.rs
+//! Commands to the motor controller on the Arduino, sent as binary frames
+//! (see [`crate::telemetry::binary`]) with message id `MSG_COMMAND`. The
+//! payload is a command code followed by its arguments:
+//!
+//! ```text
+//! 0x01 set power   motor (1) | percent, -100 to 100 (f32)
+//! 0x02 set RPM     motor (1) | rpm (f32)
+//! 0x03 stop
+//! 0x04 reset rotation counters
+//! 0x05 ping
+//! ```
+//!
+//! The Arduino answers each command frame with `MSG_ACK`, whose payload is
+//! the sequence number of the command frame and a status byte, zero for
+//! success or a firmware error code. Commands that aren't answered in time
+//! are sent again in a new frame.
+pub mod pid;
+
+use std::fmt;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::telemetry::binary::{BinaryFrame, FrameEncoder, MSG_ACK, MSG_COMMAND};
+
+/// How long to wait for the Arduino to acknowledge a command
+const ACK_TIMEOUT: Duration = Duration::from_millis(250);
+/// Times a command is sent before we give up on it
+const MAX_ATTEMPTS: u32 = 3;
+
+const CMD_SET_POWER: u8 = 0x01;
+const CMD_SET_RPM: u8 = 0x02;
+const CMD_STOP: u8 = 0x03;
+const CMD_RESET_COUNTERS: u8 = 0x04;
+const CMD_PING: u8 = 0x05;
+
+const ACK_OK: u8 = 0x00;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotorCommand {
+    /// Drive the ESC directly, -100 (full reverse) to 100 (full ahead)
+    SetPower {
+        motor: u8,
+        percent: f32,
+    },
+    /// Have the firmware hold an RPM itself
+    SetRpm {
+        motor: u8,
+        rpm: f32,
+    },
+    /// Stop both motors
+    Stop,
+    ResetCounters,
+    /// Check the firmware is listening
+    Ping,
+}
+
+impl MotorCommand {
+    /// The payload of the command frame. Power is clamped to -100..100.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        match *self {
+            MotorCommand::SetPower { motor, percent } => {
+                payload.extend([CMD_SET_POWER, motor]);
+                payload.extend(percent.clamp(-100.0, 100.0).to_le_bytes());
+            }
+            MotorCommand::SetRpm { motor, rpm } => {
+                payload.extend([CMD_SET_RPM, motor]);
+                payload.extend(rpm.to_le_bytes());
+            }
+            MotorCommand::Stop => payload.push(CMD_STOP),
+            MotorCommand::ResetCounters => payload.push(CMD_RESET_COUNTERS),
+            MotorCommand::Ping => payload.push(CMD_PING),
+        }
+        payload
+    }
+
+    /// Whether `other` makes this command pointless to retry, i.e. it's a
+    /// newer setting for the same thing
+    fn superseded_by(&self, other: &MotorCommand) -> bool {
+        match (self, other) {
+            (
+                MotorCommand::SetPower { motor, .. } | MotorCommand::SetRpm { motor, .. },
+                MotorCommand::SetPower { motor: other, .. }
+                | MotorCommand::SetRpm { motor: other, .. },
+            ) => motor == other,
+            (MotorCommand::SetPower { .. } | MotorCommand::SetRpm { .. }, MotorCommand::Stop) => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for MotorCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MotorCommand::SetPower { motor, percent } => {
+                write!(f, "Set motor {} power to {:.1}%", motor, percent)
+            }
+            MotorCommand::SetRpm { motor, rpm } => {
+                write!(f, "Set motor {} to {:.0} RPM", motor, rpm)
+            }
+            MotorCommand::Stop => write!(f, "Stop"),
+            MotorCommand::ResetCounters => write!(f, "Reset rotation counters"),
+            MotorCommand::Ping => write!(f, "Ping"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandResult {
+    Ack,
+    /// The firmware's error code
+    Nak(u8),
+    Timeout,
+}
+
+/// How a command we sent turned out
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandOutcome {
+    pub command: MotorCommand,
+    pub result: CommandResult,
+    pub attempts: u32,
+    /// From first sending the command to the answer or giving up
+    pub elapsed: Duration,
+}
+
+impl CommandOutcome {
+    /// For the log, e.g. "Ping: Ack after 12 ms (1 attempt)"
+    pub fn message(&self) -> String {
+        format!(
+            "{}: {:?} after {} ms ({} attempt{})",
+            self.command,
+            self.result,
+            self.elapsed.as_millis(),
+            self.attempts,
+            if self.attempts == 1 { "" } else { "s" }
+        )
+    }
+}
+
+#[derive(Debug)]
+struct PendingCommand {
+    command: MotorCommand,
+    /// Sequence numbers of every frame this command went out in, since the
+    /// answer to an earlier attempt may still turn up
+    seqs: Vec<u8>,
+    first_sent: Instant,
+    last_sent: Instant,
+}
+
+/// Numbers our command frames and keeps track of the ones still waiting
+/// for an answer
+#[derive(Debug, Default)]
+pub struct CommandTracker {
+    encoder: FrameEncoder,
+    pending: Vec<PendingCommand>,
+}
+
+impl CommandTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write `command` to `port`. A pending command it supersedes, e.g. an
+    /// older power setting for the same motor, isn't retried any more.
+    pub fn send<W: Write>(
+        &mut self,
+        port: &mut W,
+        command: MotorCommand,
+        now: Instant,
+    ) -> io::Result<()> {
+        self.pending
+            .retain(|pending| !pending.command.superseded_by(&command));
+        let seq = self.write(port, &command)?;
+        self.pending.push(PendingCommand {
+            command,
+            seqs: vec![seq],
+            first_sent: now,
+            last_sent: now,
+        });
+        Ok(())
+    }
+
+    fn write<W: Write>(&mut self, port: &mut W, command: &MotorCommand) -> io::Result<u8> {
+        let seq = self.encoder.next_seq();
+        port.write_all(&self.encoder.encode(MSG_COMMAND, &command.payload()))?;
+        port.flush()?;
+        Ok(seq)
+    }
+
+    /// Match an acknowledgement from the Arduino to the command it answers
+    pub fn acknowledge(&mut self, frame: &BinaryFrame, now: Instant) -> Option<CommandOutcome> {
+        if frame.id != MSG_ACK || frame.payload.len() < 2 {
+            return None;
+        }
+        let (seq, status) = (frame.payload[0], frame.payload[1]);
+        let index = self
+            .pending
+            .iter()
+            .position(|pending| pending.seqs.contains(&seq))?;
+        let pending = self.pending.remove(index);
+        Some(CommandOutcome {
+            command: pending.command,
+            result: if status == ACK_OK {
+                CommandResult::Ack
+            } else {
+                CommandResult::Nak(status)
+            },
+            attempts: pending.seqs.len() as u32,
+            elapsed: now.duration_since(pending.first_sent),
+        })
+    }
+
+    /// Send again the commands that weren't answered in time, and return
+    /// the ones that have run out of attempts
+    pub fn poll<W: Write>(
+        &mut self,
+        port: &mut W,
+        now: Instant,
+    ) -> io::Result<Vec<CommandOutcome>> {
+        let mut timed_out = Vec::new();
+        let mut i = 0;
+        while i < self.pending.len() {
+            if now.duration_since(self.pending[i].last_sent) < ACK_TIMEOUT {
+                i += 1;
+                continue;
+            }
+            if self.pending[i].seqs.len() as u32 >= MAX_ATTEMPTS {
+                let pending = self.pending.remove(i);
+                timed_out.push(CommandOutcome {
+                    command: pending.command,
+                    result: CommandResult::Timeout,
+                    attempts: pending.seqs.len() as u32,
+                    elapsed: now.duration_since(pending.first_sent),
+                });
+                continue;
+            }
+            let command = self.pending[i].command;
+            let seq = self.write(port, &command)?;
+            self.pending[i].seqs.push(seq);
+            self.pending[i].last_sent = now;
+            i += 1;
+        }
+        Ok(timed_out)
+    }
+
+    /// Commands still waiting for an answer
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::binary::FrameDecoder;
+
+    fn ack(seq: u8, status: u8) -> BinaryFrame {
+        BinaryFrame {
+            id: MSG_ACK,
+            seq: 0,
+            payload: vec![seq, status],
+        }
+    }
+
+    #[test]
+    fn test_payload() {
+        let command = MotorCommand::SetPower {
+            motor: 2,
+            percent: 150.0,
+        };
+        let mut expected = vec![CMD_SET_POWER, 2];
+        expected.extend(100.0f32.to_le_bytes());
+        assert_eq!(command.payload(), expected);
+        assert_eq!(MotorCommand::Ping.payload(), [CMD_PING]);
+    }
+
+    #[test]
+    fn test_ack_and_retry() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut tracker = CommandTracker::new();
+        let mut port = Vec::new();
+
+        tracker.send(&mut port, MotorCommand::Ping, at(0)).unwrap();
+        tracker
+            .send(&mut port, MotorCommand::ResetCounters, at(0))
+            .unwrap();
+        let sent = FrameDecoder::new().push(&port, start);
+        assert_eq!(sent.len(), 2);
+        let ping = sent[0].0.as_ref().unwrap();
+        assert_eq!(ping.id, MSG_COMMAND);
+        assert_eq!(ping.payload, [CMD_PING]);
+
+        let outcome = tracker.acknowledge(&ack(ping.seq, ACK_OK), at(20)).unwrap();
+        assert_eq!(outcome.command, MotorCommand::Ping);
+        assert_eq!(outcome.result, CommandResult::Ack);
+        assert_eq!(outcome.elapsed, Duration::from_millis(20));
+        // A duplicate answer matches nothing
+        assert_eq!(tracker.acknowledge(&ack(ping.seq, ACK_OK), at(21)), None);
+
+        // The reset isn't answered, so it goes out twice more and then
+        // times out
+        port.clear();
+        assert!(tracker.poll(&mut port, at(100)).unwrap().is_empty());
+        assert!(port.is_empty());
+        assert!(tracker.poll(&mut port, at(300)).unwrap().is_empty());
+        assert!(tracker.poll(&mut port, at(600)).unwrap().is_empty());
+        assert_eq!(FrameDecoder::new().push(&port, start).len(), 2);
+        let timed_out = tracker.poll(&mut port, at(900)).unwrap();
+        assert_eq!(timed_out.len(), 1);
+        assert_eq!(timed_out[0].result, CommandResult::Timeout);
+        assert_eq!(timed_out[0].attempts, 3);
+        assert_eq!(tracker.pending(), 0);
+    }
+
+    #[test]
+    fn test_superseded() {
+        let now = Instant::now();
+        let mut tracker = CommandTracker::new();
+        let mut port = Vec::new();
+        let power = |motor, percent| MotorCommand::SetPower { motor, percent };
+
+        tracker.send(&mut port, power(1, 10.0), now).unwrap();
+        tracker.send(&mut port, power(2, 10.0), now).unwrap();
+        tracker.send(&mut port, power(1, 20.0), now).unwrap();
+        assert_eq!(tracker.pending(), 2);
+        // The answer to the first frame, the Arduino didn't get the third yet
+        assert_eq!(tracker.acknowledge(&ack(0, ACK_OK), now), None);
+
+        let nak = tracker.acknowledge(&ack(2, 0x02), now).unwrap();
+        assert_eq!(nak.command, power(1, 20.0));
+        assert_eq!(nak.result, CommandResult::Nak(0x02));
+
+        tracker.send(&mut port, MotorCommand::Stop, now).unwrap();
+        assert_eq!(tracker.pending(), 1);
+    }
+}
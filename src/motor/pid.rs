@@ -0,0 +1,440 @@
+// src/motor/pid.rs
+//! Closed loop RPM control. Each motor has a PID loop fed by the RPM the
+//! Arduino reports, whose output is the ESC power sent back with
+//! `MotorCommand::SetPower`.
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+use super::MotorCommand;
+use crate::telemetry::TelemetryRecord;
+
+/// A gap between measurements longer than this restarts the loop's
+/// integral and derivative rather than acting on stale data
+const MAX_DT: Duration = Duration::from_secs(1);
+
+/// Loop gains and output limit. The output is ESC power in percent.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PidGains {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+    /// Feed-forward, percent per RPM of setpoint, so the loop only has to
+    /// correct the error rather than find the whole output
+    pub kf: f64,
+    /// The output is kept within ±this
+    pub output_limit: f64,
+}
+
+impl Default for PidGains {
+    /// No gains, so nothing moves until they're configured
+    fn default() -> Self {
+        Self {
+            kp: 0.0,
+            ki: 0.0,
+            kd: 0.0,
+            kf: 0.0,
+            output_limit: 100.0,
+        }
+    }
+}
+
+impl PidGains {
+    pub fn is_zero(&self) -> bool {
+        self.kp == 0.0 && self.ki == 0.0 && self.kd == 0.0 && self.kf == 0.0
+    }
+}
+
+/// One step of the loop, with the terms that made up the output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PidOutput {
+    pub output: f64,
+    pub proportional: f64,
+    pub integral: f64,
+    pub derivative: f64,
+    pub feed_forward: f64,
+    /// The output was clipped to the limit
+    pub saturated: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pid {
+    gains: PidGains,
+    setpoint: f64,
+    /// The integral term itself, with `ki` already applied, so changing
+    /// `ki` doesn't make the output jump
+    integral: f64,
+    /// The last measurement and when it was taken
+    last: Option<(f64, Instant)>,
+}
+
+impl Pid {
+    pub fn new(gains: PidGains, setpoint: f64) -> Self {
+        Self {
+            gains,
+            setpoint,
+            integral: 0.0,
+            last: None,
+        }
+    }
+
+    pub fn gains(&self) -> PidGains {
+        self.gains
+    }
+
+    /// Change the gains while running. The integral built up so far is kept.
+    pub fn set_gains(&mut self, gains: PidGains) {
+        self.gains = gains;
+        let limit = gains.output_limit;
+        self.integral = self.integral.clamp(-limit, limit);
+    }
+
+    pub fn setpoint(&self) -> f64 {
+        self.setpoint
+    }
+
+    pub fn set_setpoint(&mut self, setpoint: f64) {
+        self.setpoint = setpoint;
+    }
+
+    /// Forget the integral and last measurement, e.g. after the motor was
+    /// stopped by something else
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.last = None;
+    }
+
+    /// Work out the output for a measurement taken at `now`
+    pub fn update(&mut self, measurement: f64, now: Instant) -> PidOutput {
+        let gains = self.gains;
+        let limit = gains.output_limit;
+        let error = self.setpoint - measurement;
+        let gap = self.last.map(|(_, at)| now.saturating_duration_since(at));
+        if gap.is_some_and(|gap| gap > MAX_DT) {
+            self.integral = 0.0;
+        }
+        let dt = gap
+            .filter(|dt| !dt.is_zero() && *dt <= MAX_DT)
+            .map(|dt| dt.as_secs_f64());
+
+        let proportional = gains.kp * error;
+        let feed_forward = gains.kf * self.setpoint;
+        // On the measurement rather than the error, so a setpoint change
+        // doesn't kick the output
+        let derivative = match (dt, self.last) {
+            (Some(dt), Some((last, _))) => -gains.kd * (measurement - last) / dt,
+            _ => 0.0,
+        };
+        if let Some(dt) = dt {
+            // Anti-windup: stop integrating while the output is saturated in
+            // the direction the error would push it
+            let integral = self.integral + gains.ki * error * dt;
+            let unclamped = proportional + integral + derivative + feed_forward;
+            let winding_up =
+                (unclamped > limit && error > 0.0) || (unclamped < -limit && error < 0.0);
+            if !winding_up {
+                self.integral = integral.clamp(-limit, limit);
+            }
+        }
+        self.last = Some((measurement, now));
+
+        let unclamped = proportional + self.integral + derivative + feed_forward;
+        let output = unclamped.clamp(-limit, limit);
+        PidOutput {
+            output,
+            proportional,
+            integral: self.integral,
+            derivative,
+            feed_forward,
+            saturated: output != unclamped,
+        }
+    }
+}
+
+/// The `[motor_control]` section of the config file. A motor without a
+/// setpoint isn't controlled.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MotorControlConfig {
+    pub motor_1_rpm: Option<f64>,
+    pub motor_2_rpm: Option<f64>,
+    pub gains: PidGains,
+}
+
+impl MotorControlConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.motor_1_rpm.is_some() || self.motor_2_rpm.is_some()
+    }
+
+    fn setpoints(&self) -> [(u8, Option<f64>); 2] {
+        [(1, self.motor_1_rpm), (2, self.motor_2_rpm)]
+    }
+}
+
+/// What one motor's loop did with a line of telemetry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotorOutput {
+    pub motor: u8,
+    pub setpoint: f64,
+    pub measured: f64,
+    pub pid: PidOutput,
+}
+
+impl MotorOutput {
+    /// The command that applies the output
+    pub fn command(&self) -> MotorCommand {
+        MotorCommand::SetPower {
+            motor: self.motor,
+            percent: self.pid.output as f32,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MotorLoop {
+    motor: u8,
+    /// The telemetry field with this motor's RPM, e.g. `motor_1_rpm`
+    field: String,
+    pid: Pid,
+}
+
+/// A PID loop for each motor with a setpoint
+#[derive(Debug, Clone)]
+pub struct RpmController {
+    loops: Vec<MotorLoop>,
+    /// When an RPM reading last came in, while the motors are being driven
+    last_reading: Option<Instant>,
+}
+
+impl RpmController {
+    pub fn new(config: &MotorControlConfig) -> Self {
+        let loops = config
+            .setpoints()
+            .into_iter()
+            .filter_map(|(motor, setpoint)| {
+                Some(MotorLoop {
+                    motor,
+                    field: format!("motor_{}_rpm", motor),
+                    pid: Pid::new(config.gains, setpoint?),
+                })
+            })
+            .collect();
+        Self {
+            loops,
+            last_reading: None,
+        }
+    }
+
+    /// Take new gains and setpoints without restarting the loops. Motors
+    /// whose setpoint was removed stop being controlled.
+    pub fn retune(&mut self, config: &MotorControlConfig) {
+        let setpoints = config.setpoints();
+        self.loops.retain_mut(|motor_loop| {
+            let Some((_, Some(setpoint))) = setpoints
+                .iter()
+                .find(|(motor, _)| *motor == motor_loop.motor)
+            else {
+                return false;
+            };
+            motor_loop.pid.set_gains(config.gains);
+            motor_loop.pid.set_setpoint(*setpoint);
+            true
+        });
+        for new_loop in RpmController::new(config).loops {
+            if !self
+                .loops
+                .iter()
+                .any(|motor_loop| motor_loop.motor == new_loop.motor)
+            {
+                self.loops.push(new_loop);
+            }
+        }
+        self.loops.sort_by_key(|motor_loop| motor_loop.motor);
+    }
+
+    /// Restart every loop, e.g. after the Arduino was reset
+    pub fn reset(&mut self) {
+        for motor_loop in &mut self.loops {
+            motor_loop.pid.reset();
+        }
+    }
+
+    /// The motors being controlled
+    pub fn motors(&self) -> Vec<u8> {
+        self.loops
+            .iter()
+            .map(|motor_loop| motor_loop.motor)
+            .collect()
+    }
+
+    /// Whether the RPM readings have stopped for longer than `MAX_DT`, so
+    /// the motors should be stopped rather than left at their last output.
+    /// True once per outage, and the loops start over when readings return.
+    pub fn readings_lost(&mut self, now: Instant) -> bool {
+        let lost = self
+            .last_reading
+            .is_some_and(|at| now.saturating_duration_since(at) > MAX_DT);
+        if lost {
+            self.last_reading = None;
+            self.reset();
+        }
+        lost
+    }
+
+    /// Run each loop on the RPM in `record`, read at `now`. Motors missing
+    /// from the record are skipped.
+    pub fn update(&mut self, record: &TelemetryRecord, now: Instant) -> Vec<MotorOutput> {
+        let outputs: Vec<MotorOutput> = self
+            .loops
+            .iter_mut()
+            .filter_map(|motor_loop| {
+                let measured = record.get(&motor_loop.field)?;
+                Some(MotorOutput {
+                    motor: motor_loop.motor,
+                    setpoint: motor_loop.pid.setpoint(),
+                    measured,
+                    pid: motor_loop.pid.update(measured, now),
+                })
+            })
+            .collect();
+        if !outputs.is_empty() {
+            self.last_reading = Some(now);
+        }
+        outputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::Schema;
+    use std::sync::Arc;
+
+    fn gains(kp: f64, ki: f64, kd: f64, kf: f64) -> PidGains {
+        PidGains {
+            kp,
+            ki,
+            kd,
+            kf,
+            output_limit: 100.0,
+        }
+    }
+
+    #[test]
+    fn test_pid_terms() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut pid = Pid::new(gains(0.125, 0.25, 0.0625, 0.03125), 1024.0);
+
+        // No integral or derivative until there's a time step
+        let first = pid.update(768.0, at(0));
+        assert_eq!(first.proportional, 32.0);
+        assert_eq!(first.feed_forward, 32.0);
+        assert_eq!(first.integral, 0.0);
+        assert_eq!(first.output, 64.0);
+
+        let second = pid.update(896.0, at(500));
+        assert_eq!(second.proportional, 16.0);
+        assert_eq!(second.integral, 16.0);
+        assert_eq!(second.derivative, -16.0);
+        assert_eq!(second.output, 48.0);
+        assert!(!second.saturated);
+
+        // Changing ki keeps the integral term, so the output doesn't jump
+        pid.set_gains(gains(0.125, 1.0, 0.0625, 0.03125));
+        assert_eq!(pid.update(896.0, at(500)).integral, 16.0);
+
+        // A long gap restarts the integral and derivative
+        let stale = pid.update(960.0, at(5000));
+        assert_eq!(stale.integral, 0.0);
+        assert_eq!(stale.derivative, 0.0);
+    }
+
+    #[test]
+    fn test_anti_windup() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut pid = Pid::new(gains(0.5, 1.0, 0.0, 0.0), 1000.0);
+
+        // The motor is stalled, so the output saturates
+        for i in 0..=100 {
+            let step = pid.update(0.0, at(i * 100));
+            assert!(step.output <= 100.0);
+        }
+        let stalled = pid.update(0.0, at(10_100));
+        assert!(stalled.saturated);
+        assert_eq!(stalled.output, 100.0);
+        // The integral stopped growing when the output hit the limit
+        assert_eq!(stalled.integral, 0.0);
+
+        // Once the motor overshoots the output comes straight back down
+        let overshoot = pid.update(1100.0, at(10_200));
+        assert!(overshoot.output < 0.0);
+    }
+
+    #[test]
+    fn test_controller() {
+        let now = Instant::now();
+        let config = MotorControlConfig {
+            motor_1_rpm: Some(1024.0),
+            motor_2_rpm: None,
+            gains: gains(0.125, 0.0, 0.0, 0.0625),
+        };
+        let mut controller = RpmController::new(&config);
+        assert_eq!(controller.motors(), [1]);
+
+        let schema = Arc::new(Schema::legacy());
+        let record = schema
+            .parse_record("1000,900,1.5,2.5,1234,100,120,500,300,310")
+            .unwrap();
+        let outputs = controller.update(&record, now);
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].measured, 1000.0);
+        assert_eq!(outputs[0].pid.output, 67.0);
+        assert_eq!(
+            outputs[0].command(),
+            MotorCommand::SetPower {
+                motor: 1,
+                percent: 67.0
+            }
+        );
+
+        controller.retune(&MotorControlConfig {
+            motor_1_rpm: None,
+            motor_2_rpm: Some(800.0),
+            ..config
+        });
+        assert_eq!(controller.motors(), [2]);
+        let outputs = controller.update(&record, now);
+        assert_eq!(outputs[0].setpoint, 800.0);
+        assert_eq!(outputs[0].measured, 900.0);
+    }
+
+    #[test]
+    fn test_readings_lost() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut controller = RpmController::new(&MotorControlConfig {
+            motor_1_rpm: Some(1000.0),
+            motor_2_rpm: None,
+            gains: gains(0.0, 1.0, 0.0, 0.0),
+        });
+        let schema = Arc::new(Schema::legacy());
+        let record = schema
+            .parse_record("900,900,1.5,2.5,1234,100,120,500,300,310")
+            .unwrap();
+
+        // Nothing to stop before the motors were driven
+        assert!(!controller.readings_lost(at(5000)));
+        controller.update(&record, at(5000));
+        controller.update(&record, at(5500));
+        assert!(!controller.readings_lost(at(6000)));
+        assert!(controller.readings_lost(at(6600)));
+        // Only once per outage
+        assert!(!controller.readings_lost(at(8000)));
+
+        // The integral built up before starts over
+        let outputs = controller.update(&record, at(9000));
+        assert_eq!(outputs[0].pid.integral, 0.0);
+    }
+}
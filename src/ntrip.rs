@@ -1,72 +1,719 @@
 // src/ntrip.rs
+pub mod caster;
+pub mod http;
+pub mod sourcetable;
+
 use base64::{Engine as _, engine::general_purpose};
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use serde::Deserialize;
 use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
-use std::net::TcpStream;
+use std::net::{TcpStream, ToSocketAddrs};
 use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use http::{ChunkState, NtripResponse};
+use sourcetable::{Sourcetable, StreamRecord};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// If no data arrives for this long the link is considered dead
+const READ_TIMEOUT: Duration = Duration::from_secs(15);
+/// Socket timeout while streaming, so GGA can be sent between reads
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// GGA interval used for mountpoints that need it when none is configured
+const DEFAULT_GGA_INTERVAL: Duration = Duration::from_secs(10);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How often to check for a fix while waiting to pick a mountpoint
+const FIX_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Connection settings for an NTRIP caster.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NtripConfig {
+    /// Caster hostname, e.g. rtk2go.com
+    pub host: String,
+    /// Caster port, usually 2101 (or 443 with TLS)
+    pub port: u16,
+    /// Mountpoint to request, e.g. VMAX-LAND-1
+    pub mountpoint: String,
+    pub username: String,
+    pub password: String,
+    /// Wrap the connection in TLS (for casters served over HTTPS)
+    pub tls: bool,
+    /// NTRIP protocol version to speak
+    pub version: NtripVersion,
+    /// Ignore `mountpoint` and pick the nearest RTCM3 stream from the
+    /// sourcetable once the rover has a fix
+    pub auto_mountpoint: bool,
+    /// How often to send the rover's GGA position to the caster, which
+    /// network (VRS) mountpoints need. 0 disables it.
+    pub gga_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum NtripVersion {
+    /// Try NTRIP 2.0 first and fall back to 1.0 if the caster doesn't speak it
+    #[default]
+    Auto,
+    V1,
+    V2,
+}
+
+impl Default for NtripConfig {
+    fn default() -> Self {
+        Self {
+            host: "rtk2go.com".to_string(),
+            port: 2101,
+            mountpoint: String::new(),
+            username: String::new(),
+            password: "none".to_string(), // typically 'none' for RTK2GO
+            tls: false,
+            version: NtripVersion::Auto,
+            auto_mountpoint: false,
+            gga_interval_secs: 0,
+        }
+    }
+}
+
+/// A connection to the caster, either plain TCP or TLS.
+pub enum NtripStream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Read for NtripStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            NtripStream::Plain(stream) => stream.read(buf),
+            NtripStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl NtripStream {
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            NtripStream::Plain(stream) => stream.set_read_timeout(timeout),
+            NtripStream::Tls(stream) => stream.sock.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Write for NtripStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            NtripStream::Plain(stream) => stream.write(buf),
+            NtripStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            NtripStream::Plain(stream) => stream.flush(),
+            NtripStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+fn wrap_tls(host: &str, tcp: TcpStream) -> io::Result<NtripStream> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let tls_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(host.to_string())
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+    let connection =
+        ClientConnection::new(Arc::new(tls_config), server_name).map_err(io::Error::other)?;
+    Ok(NtripStream::Tls(Box::new(StreamOwned::new(
+        connection, tcp,
+    ))))
+}
+
+/// Open the TCP (and optionally TLS) connection to the caster
+pub fn open_stream(config: &NtripConfig) -> io::Result<NtripStream> {
+    let mut last_error = io::Error::new(
+        ErrorKind::NotFound,
+        format!("Could not resolve {}", config.host),
+    );
+    for addr in (config.host.as_str(), config.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(tcp) => {
+                tcp.set_read_timeout(Some(READ_TIMEOUT))?;
+                return if config.tls {
+                    wrap_tls(&config.host, tcp)
+                } else {
+                    Ok(NtripStream::Plain(tcp))
+                };
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
 
-pub fn connect_rtk2go_ntrip(tx: Sender<Vec<u8>>, mountpoint: &str) -> () {
-    // Connect to the RTK2GO NTRIP caster
-    let maybe_stream = TcpStream::connect(("rtk2go.com", 2101));
-    let mut stream = maybe_stream.expect("Could not connect to RTK2GO!");
+/// An accepted mountpoint request. Reading yields the RTCM data with any
+/// chunked transfer encoding removed; writes go straight to the caster.
+pub struct NtripConnection {
+    stream: NtripStream,
+    chunks: Option<ChunkState>,
+}
+
+impl Read for NtripConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.chunks {
+            Some(state) => http::read_chunked(&mut self.stream, state, buf),
+            None => self.stream.read(buf),
+        }
+    }
+}
+
+impl Write for NtripConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
 
+/// Build the GET request for `path` in the given protocol version
+fn build_request(config: &NtripConfig, path: &str, version: NtripVersion) -> String {
     // Prepare the auth header
-    let auth = format!("{}:{}", "hoppingturtles@proton.me", "none");
+    let auth = format!("{}:{}", config.username, config.password);
     let auth_b64 = general_purpose::STANDARD.encode(auth);
 
-    // Construct NTRIP request header
-    let request = format!(
-        "GET /{} HTTP/1.0\r\n\
-        User-Agent: NTRIP RustClient\r\n\
-        Accept: */*\r\n\
-        Authorization: Basic {}\r\n\
-        Connection: close\r\n\r\n",
-        mountpoint, auth_b64
+    // v1 casters require the User-Agent to start with "NTRIP"
+    match version {
+        NtripVersion::V2 => format!(
+            "GET /{} HTTP/1.1\r\n\
+            Host: {}\r\n\
+            Ntrip-Version: Ntrip/2.0\r\n\
+            User-Agent: NTRIP RustClient\r\n\
+            Accept: */*\r\n\
+            Authorization: Basic {}\r\n\
+            Connection: close\r\n\r\n",
+            path, config.host, auth_b64
+        ),
+        NtripVersion::V1 | NtripVersion::Auto => format!(
+            "GET /{} HTTP/1.0\r\n\
+            User-Agent: NTRIP RustClient\r\n\
+            Accept: */*\r\n\
+            Authorization: Basic {}\r\n\
+            Connection: close\r\n\r\n",
+            path, auth_b64
+        ),
+    }
+}
+
+/// Send a request for `path` and read the response header.
+///
+/// A v2 request that the caster clearly didn't understand fails with
+/// `ErrorKind::Unsupported`, so the caller can retry with v1.
+pub fn send_request(
+    stream: &mut NtripStream,
+    config: &NtripConfig,
+    path: &str,
+    version: NtripVersion,
+) -> io::Result<NtripResponse> {
+    let request = build_request(config, path, version);
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+
+    match http::read_response(stream) {
+        Ok(response) => {
+            if version == NtripVersion::V2 && matches!(response.status_code, 400 | 501 | 505) {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "Caster rejected NTRIP v2 request: {} {}",
+                        response.status_code, response.reason
+                    ),
+                ));
+            }
+            Ok(response)
+        }
+        Err(e) if version == NtripVersion::V2 && e.kind() == ErrorKind::InvalidData => {
+            Err(io::Error::new(ErrorKind::Unsupported, e.to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Request the configured mountpoint using the given protocol version and
+/// check that the caster accepted it.
+pub fn request_mountpoint(
+    mut stream: NtripStream,
+    config: &NtripConfig,
+    version: NtripVersion,
+) -> io::Result<NtripConnection> {
+    let response = send_request(&mut stream, config, &config.mountpoint, version)?;
+
+    match (response.protocol.as_str(), response.status_code) {
+        // v1 casters answer an unknown mountpoint with their sourcetable
+        ("SOURCETABLE", _) => Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("Mountpoint {} not found on caster", config.mountpoint),
+        )),
+        (_, 200) => {
+            let chunks = response.is_chunked().then(ChunkState::default);
+            Ok(NtripConnection { stream, chunks })
+        }
+        (_, 401) => Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "NTRIP authentication failed: {} {}",
+                response.status_code, response.reason
+            ),
+        )),
+        (_, 404) => Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("Mountpoint {} not found on caster", config.mountpoint),
+        )),
+        _ => Err(io::Error::new(
+            ErrorKind::ConnectionRefused,
+            format!(
+                "NTRIP connection failed: {} {}",
+                response.status_code, response.reason
+            ),
+        )),
+    }
+}
+
+/// Request the mountpoint in the configured version, falling back from v2 to
+/// v1 on a fresh connection when the version is `Auto`.
+fn negotiate(stream: NtripStream, config: &NtripConfig) -> io::Result<NtripConnection> {
+    match config.version {
+        NtripVersion::Auto => match request_mountpoint(stream, config, NtripVersion::V2) {
+            Err(e) if e.kind() == ErrorKind::Unsupported => {
+                eprintln!("{}. Falling back to NTRIP v1", e);
+                request_mountpoint(open_stream(config)?, config, NtripVersion::V1)
+            }
+            result => result,
+        },
+        version => request_mountpoint(stream, config, version),
+    }
+}
+
+/// Connect to the caster and request the configured mountpoint. The returned
+/// connection is positioned at the start of the RTCM data.
+pub fn connect(config: &NtripConfig) -> io::Result<NtripConnection> {
+    negotiate(open_stream(config)?, config)
+}
+
+/// Download and parse the caster's sourcetable
+pub fn fetch_sourcetable(config: &NtripConfig) -> io::Result<Sourcetable> {
+    let mut stream = open_stream(config)?;
+    let version = match config.version {
+        NtripVersion::V1 => NtripVersion::V1,
+        _ => NtripVersion::V2,
+    };
+    let response = match send_request(&mut stream, config, "", version) {
+        Err(e) if e.kind() == ErrorKind::Unsupported && config.version == NtripVersion::Auto => {
+            stream = open_stream(config)?;
+            send_request(&mut stream, config, "", NtripVersion::V1)?
+        }
+        result => result?,
+    };
+    if response.status_code != 200 {
+        return Err(io::Error::new(
+            ErrorKind::ConnectionRefused,
+            format!(
+                "Sourcetable request failed: {} {}",
+                response.status_code, response.reason
+            ),
+        ));
+    }
+
+    let chunks = response.is_chunked().then(ChunkState::default);
+    let mut reader = BufReader::new(NtripConnection { stream, chunks });
+    let mut body = String::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        // Station names are not always valid UTF-8
+        let text = String::from_utf8_lossy(&line);
+        if text.trim() == "ENDSOURCETABLE" {
+            break;
+        }
+        body.push_str(&text);
+    }
+
+    Ok(sourcetable::parse(&body))
+}
+
+#[derive(Debug, Default)]
+struct RoverFix {
+    position: Option<(f64, f64)>,
+    gga: Option<String>,
+}
+
+/// Latest rover position, shared from the main loop with the NTRIP thread
+#[derive(Debug, Clone, Default)]
+pub struct RoverPosition {
+    inner: Arc<Mutex<RoverFix>>,
+}
+
+impl RoverPosition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&self, latitude: f64, longitude: f64) {
+        self.inner.lock().unwrap().position = Some((latitude, longitude));
+    }
+
+    /// Latitude and longitude of the last fix, if there has been one
+    pub fn latest(&self) -> Option<(f64, f64)> {
+        self.inner.lock().unwrap().position
+    }
+
+    /// Store the last GGA sentence from the receiver, including its `\r\n`
+    pub fn update_gga(&self, sentence: &str) {
+        self.inner.lock().unwrap().gga = Some(sentence.to_string());
+    }
+
+    pub fn latest_gga(&self) -> Option<String> {
+        self.inner.lock().unwrap().gga.clone()
+    }
+}
+
+/// Pick the nearest RTCM3 mountpoint to the rover. The sourcetable is only
+/// downloaded if `sourcetable` doesn't already hold it.
+fn select_mountpoint(
+    config: &NtripConfig,
+    sourcetable: &mut Option<Sourcetable>,
+    latitude: f64,
+    longitude: f64,
+) -> io::Result<StreamRecord> {
+    let table = match sourcetable {
+        Some(table) => table,
+        None => sourcetable.insert(fetch_sourcetable(config)?),
+    };
+    let (stream, distance) = table.nearest_rtcm3(latitude, longitude).ok_or_else(|| {
+        io::Error::new(ErrorKind::NotFound, "Sourcetable has no RTCM3 mountpoints")
+    })?;
+    println!(
+        "Selected NTRIP mountpoint {} ({}, {:.1} km away)",
+        stream.mountpoint, stream.format, distance
     );
+    Ok(stream.clone())
+}
+
+/// Where the NTRIP client currently is in its connection cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NtripState {
+    /// Opening the TCP/TLS connection
+    #[default]
+    Connecting,
+    /// Connected, waiting for the caster to accept the mountpoint request
+    Authenticating,
+    /// Receiving RTCM data
+    Streaming,
+    /// Waiting before the next connection attempt
+    BackingOff,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NtripStatus {
+    pub state: NtripState,
+    /// Number of times the connection was lost or refused
+    pub failures: u32,
+    pub last_error: Option<String>,
+    /// Mountpoint currently in use
+    pub mountpoint: Option<String>,
+}
+
+/// Shared view of the NTRIP client status, cheap to clone across threads
+#[derive(Debug, Clone, Default)]
+pub struct NtripStatusHandle {
+    inner: Arc<Mutex<NtripStatus>>,
+}
 
-    if let Err(e) = stream.write_all(request.as_bytes()) {
-        eprintln!("Failed to send NTRIP request: {:?}", e);
-        return;
+impl NtripStatusHandle {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    // Check if our server accepted the connection:
-    let mut reader = BufReader::new(&stream);
-    let mut line = String::new();
+    /// Snapshot of the current status
+    pub fn get(&self) -> NtripStatus {
+        self.inner.lock().unwrap().clone()
+    }
+
+    fn set_state(&self, state: NtripState) {
+        self.inner.lock().unwrap().state = state;
+    }
 
-    // Read status line
-    if let Err(e) = reader.read_line(&mut line) {
-        eprintln!("Failed to read NTRIP response: {:?}", e);
-        return;
+    fn set_mountpoint(&self, mountpoint: &str) {
+        self.inner.lock().unwrap().mountpoint = Some(mountpoint.to_string());
     }
 
-    if !line.contains("200 OK") && !line.contains("ICY 200 OK") {
-        // Some servers use ICY
-        eprintln!("NTRIP connection failed: {}", line.trim());
+    fn record_failure(&self, error: &io::Error) {
+        let mut status = self.inner.lock().unwrap();
+        status.state = NtripState::BackingOff;
+        status.failures += 1;
+        status.last_error = Some(error.to_string());
+    }
+}
+
+/// Exponential backoff between reconnection attempts
+struct Backoff {
+    current: Duration,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self {
+            current: INITIAL_BACKOFF,
+        }
     }
 
-    // Read and print incoming RTCM data chunks
+    /// Delay to wait now, doubling the one after it (up to `MAX_BACKOFF`)
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(MAX_BACKOFF);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.current = INITIAL_BACKOFF;
+    }
+}
+
+/// Forward RTCM data to `tx` until the connection fails, sending the rover's
+/// GGA sentence upstream every `gga_interval`. Returns `Ok` only if the
+/// receiving end of the channel has gone away.
+///
+/// `backoff` is reset once data arrives, so a caster that accepts the
+/// request and then hangs up straight away is still retried less and less
+/// often.
+fn stream_corrections(
+    connection: &mut NtripConnection,
+    tx: &Sender<Vec<u8>>,
+    position: &RoverPosition,
+    gga_interval: Option<Duration>,
+    backoff: &mut Backoff,
+) -> io::Result<()> {
+    connection.stream.set_read_timeout(Some(POLL_INTERVAL))?;
+
     let mut buf = [0u8; 4096];
+    let mut last_data = Instant::now();
+    let mut last_gga: Option<Instant> = None;
+    let mut got_data = false;
     loop {
-        let n = stream.read(&mut buf);
+        if let Some(interval) = gga_interval
+            && last_gga.is_none_or(|sent| sent.elapsed() >= interval)
+            && let Some(gga) = position.latest_gga()
+        {
+            connection.write_all(gga.as_bytes())?;
+            connection.flush()?;
+            last_gga = Some(Instant::now());
+        }
 
-        match n {
-            Ok(n) => {
-                if n == 0 {
-                    eprintln!("NTRIP stream closed");
+        let n = match connection.read(&mut buf) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "NTRIP stream closed",
+                ));
+            }
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            // A read timeout shows up as WouldBlock on Unix and TimedOut on Windows
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if last_data.elapsed() < READ_TIMEOUT {
+                    continue;
                 }
-                // Here you can process or forward the data. We'll just print length for this example:
-                // println!("Received {} bytes of RTCM data", n);
-
-                let _ = tx
-                    .send(buf[0..n].to_vec())
-                    .map_err(|e| io::Error::new(ErrorKind::Other, e));
+                return Err(io::Error::new(
+                    ErrorKind::TimedOut,
+                    format!("No NTRIP data for {} seconds", READ_TIMEOUT.as_secs()),
+                ));
             }
+            Err(e) => return Err(e),
+        };
 
-            Err(e) => {
-                eprintln!("NTRIP read error: {:?}. Trying again in 5 seconds", e);
-                thread::sleep(Duration::from_secs(5));
-            }
+        if !got_data {
+            backoff.reset();
+            got_data = true;
+        }
+        last_data = Instant::now();
+        if tx.send(buf[0..n].to_vec()).is_err() {
+            return Ok(());
         }
     }
 }
+
+/// Keep a connection to the configured caster open and forward the RTCM data
+/// to `tx`, reconnecting with exponential backoff whenever it drops.
+///
+/// With `auto_mountpoint` set, the nearest mountpoint to `position` is
+/// selected again before every connection attempt, once there is a fix. The
+/// sourcetable is kept for the session, and only downloaded again if the
+/// mountpoint it offered turns out not to exist.
+pub fn run_ntrip_client(
+    tx: Sender<Vec<u8>>,
+    config: NtripConfig,
+    status: NtripStatusHandle,
+    position: RoverPosition,
+) {
+    let mut backoff = Backoff::new();
+    let mut config = config;
+    let configured_gga_interval =
+        (config.gga_interval_secs > 0).then(|| Duration::from_secs(config.gga_interval_secs));
+    let mut sourcetable = None;
+    let mut waiting_for_fix = false;
+
+    loop {
+        status.set_state(NtripState::Connecting);
+        let mut gga_interval = configured_gga_interval;
+        let result = if config.auto_mountpoint {
+            // Not a failed attempt, there's just nothing to pick by yet
+            let Some((latitude, longitude)) = position.latest() else {
+                if !waiting_for_fix {
+                    println!("Waiting for a GPS fix to select the nearest mountpoint");
+                    waiting_for_fix = true;
+                }
+                thread::sleep(FIX_POLL_INTERVAL);
+                continue;
+            };
+            select_mountpoint(&config, &mut sourcetable, latitude, longitude).map(|stream| {
+                // Network mountpoints won't send anything until they get a position
+                if stream.nmea && gga_interval.is_none() {
+                    gga_interval = Some(DEFAULT_GGA_INTERVAL);
+                }
+                config.mountpoint = stream.mountpoint;
+            })
+        } else {
+            Ok(())
+        };
+
+        let result = result
+            .and_then(|()| open_stream(&config))
+            .and_then(|stream| {
+                status.set_mountpoint(&config.mountpoint);
+                status.set_state(NtripState::Authenticating);
+                let mut connection = negotiate(stream, &config)?;
+
+                status.set_state(NtripState::Streaming);
+                stream_corrections(&mut connection, &tx, &position, gga_interval, &mut backoff)
+            });
+
+        let error = match result {
+            // Nobody is listening for corrections anymore
+            Ok(()) => return,
+            Err(e) => e,
+        };
+
+        if error.kind() == ErrorKind::NotFound {
+            sourcetable = None;
+        }
+        let delay = backoff.next_delay();
+        status.record_failure(&error);
+        eprintln!(
+            "NTRIP connection to {}:{} failed: {}. Retrying in {} seconds",
+            config.host,
+            config.port,
+            error,
+            delay.as_secs()
+        );
+        thread::sleep(delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(4));
+        for _ in 0..10 {
+            backoff.next_delay();
+        }
+        assert_eq!(backoff.next_delay(), MAX_BACKOFF);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), INITIAL_BACKOFF);
+    }
+
+    #[test]
+    fn test_select_mountpoint_uses_cached_sourcetable() {
+        // Nothing listens here, so this only works without a download
+        let config = NtripConfig {
+            host: "127.0.0.1".to_string(),
+            port: 1,
+            ..NtripConfig::default()
+        };
+        let mut table = Some(sourcetable::parse(
+            "STR;RALEIGH;Raleigh;RTCM 3.2;1005(10),1077(1);2;GPS+GLO;SNIP;USA;35.78;-78.64;0;0;\
+             sNTRIP;none;B;N;9600;\r\nENDSOURCETABLE\r\n",
+        ));
+        let stream = select_mountpoint(&config, &mut table, 35.7, -78.6).unwrap();
+        assert_eq!(stream.mountpoint, "RALEIGH");
+        assert!(select_mountpoint(&config, &mut None, 35.7, -78.6).is_err());
+    }
+
+    /// Serve one mountpoint request on a local port: accept it, send `data`
+    /// and hang up. Returns how long the client would back off afterwards
+    /// (having already backed off twice) and the data it forwarded.
+    fn stream_from_local_caster(data: &'static [u8]) -> (Duration, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let caster = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request);
+            socket.write_all(b"ICY 200 OK\r\n").unwrap();
+            socket.write_all(data).unwrap();
+        });
+
+        let config = NtripConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            mountpoint: "TEST".to_string(),
+            version: NtripVersion::V1,
+            ..NtripConfig::default()
+        };
+        let mut connection = connect(&config).unwrap();
+        let mut backoff = Backoff::new();
+        backoff.next_delay();
+        backoff.next_delay();
+        let (tx, rx) = mpsc::channel();
+        let result = stream_corrections(
+            &mut connection,
+            &tx,
+            &RoverPosition::new(),
+            None,
+            &mut backoff,
+        );
+        caster.join().unwrap();
+        assert!(result.is_err());
+        (backoff.next_delay(), rx.try_iter().flatten().collect())
+    }
+
+    #[test]
+    fn test_backoff_resets_only_after_data() {
+        // Accepted and closed straight away, e.g. a banned client
+        assert_eq!(
+            stream_from_local_caster(b""),
+            (Duration::from_secs(4), Vec::new())
+        );
+        assert_eq!(
+            stream_from_local_caster(&[0xD3, 0x00, 0x00]),
+            (INITIAL_BACKOFF, vec![0xD3, 0x00, 0x00])
+        );
+    }
+}
@@ -0,0 +1,447 @@
+// src/ntrip/caster.rs
+use base64::{Engine as _, engine::general_purpose};
+use serde::Deserialize;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::http::read_header_line;
+
+/// Messages buffered per client before we start dropping data for it
+const CLIENT_QUEUE_LEN: usize = 64;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings for the built-in caster that re-serves our corrections on the LAN
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CasterConfig {
+    /// Address to listen on
+    pub bind: String,
+    pub port: u16,
+    /// Name rovers use to request our corrections
+    pub mountpoint: String,
+    /// If either is set, rovers must log in with these credentials. Only
+    /// the ones that are set are checked.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Rovers connected at once, further ones are turned away
+    pub max_clients: usize,
+    /// Approximate position advertised in the sourcetable
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Default for CasterConfig {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0".to_string(),
+            port: 2101,
+            mountpoint: "MAARCO".to_string(),
+            username: None,
+            password: None,
+            max_clients: 16,
+            latitude: 0.0,
+            longitude: 0.0,
+        }
+    }
+}
+
+/// A rover's request, as far as we care about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientRequest {
+    /// Requested mountpoint, empty for the sourcetable
+    pub mountpoint: String,
+    /// The rover sent `Ntrip-Version: Ntrip/2.0`
+    pub v2: bool,
+    /// Username and password from a Basic `Authorization` header
+    pub credentials: Option<(String, String)>,
+}
+
+/// Read and parse a rover's GET request
+pub fn read_request<R: Read>(stream: &mut R) -> io::Result<ClientRequest> {
+    let request_line = read_header_line(stream)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some("GET"), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Unsupported request: {}", request_line.trim()),
+        ));
+    };
+
+    let mut request = ClientRequest {
+        mountpoint: path.trim_start_matches('/').to_string(),
+        v2: false,
+        credentials: None,
+    };
+    loop {
+        let line = read_header_line(stream)?;
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("Ntrip-Version") {
+            request.v2 = value.eq_ignore_ascii_case("Ntrip/2.0");
+        } else if name.eq_ignore_ascii_case("Authorization") {
+            request.credentials = value
+                .strip_prefix("Basic ")
+                .and_then(|encoded| general_purpose::STANDARD.decode(encoded.trim()).ok())
+                .and_then(|decoded| {
+                    let decoded = String::from_utf8_lossy(&decoded).into_owned();
+                    decoded
+                        .split_once(':')
+                        .map(|(user, pass)| (user.to_string(), pass.to_string()))
+                });
+        }
+    }
+
+    Ok(request)
+}
+
+/// Queue of RTCM frames for one connected rover
+type ClientQueue = SyncSender<Arc<Vec<u8>>>;
+
+/// Handle for pushing corrections to every connected rover
+#[derive(Clone, Default)]
+pub struct Caster {
+    clients: Arc<Mutex<Vec<ClientQueue>>>,
+}
+
+impl Caster {
+    /// Send an RTCM frame to all connected rovers. Rovers that have fallen
+    /// too far behind miss the frame rather than holding everyone else up.
+    pub fn broadcast(&self, data: &[u8]) {
+        let data = Arc::new(data.to_vec());
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| match client.try_send(data.clone()) {
+                Ok(()) | Err(TrySendError::Full(_)) => true,
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    fn add_client(&self) -> Receiver<Arc<Vec<u8>>> {
+        let (tx, rx) = mpsc::sync_channel(CLIENT_QUEUE_LEN);
+        self.clients.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+fn sourcetable(config: &CasterConfig) -> String {
+    let auth = if requires_login(config) { "B" } else { "N" };
+    format!(
+        "STR;{0};{0};RTCM 3;;2;GPS;MAARCO;;{1:.2};{2:.2};0;0;MAARCO;none;{3};N;0;\r\n\
+        ENDSOURCETABLE\r\n",
+        config.mountpoint, config.latitude, config.longitude, auth
+    )
+}
+
+fn send_sourcetable(stream: &mut TcpStream, config: &CasterConfig, v2: bool) -> io::Result<()> {
+    let body = sourcetable(config);
+    let status = if v2 {
+        "HTTP/1.1 200 OK\r\nNtrip-Version: Ntrip/2.0\r\nContent-Type: gnss/sourcetable\r\n"
+    } else {
+        "SOURCETABLE 200 OK\r\nContent-Type: text/plain\r\n"
+    };
+    let response = format!(
+        "{}Server: NTRIP MAARCO\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())
+}
+
+fn requires_login(config: &CasterConfig) -> bool {
+    config.username.is_some() || config.password.is_some()
+}
+
+fn is_authorized(config: &CasterConfig, request: &ClientRequest) -> bool {
+    if !requires_login(config) {
+        return true;
+    }
+    let Some((user, pass)) = &request.credentials else {
+        return false;
+    };
+    config
+        .username
+        .as_ref()
+        .is_none_or(|username| user == username)
+        && config
+            .password
+            .as_ref()
+            .is_none_or(|password| pass == password)
+}
+
+/// Answer one rover connection, streaming corrections until it disconnects
+fn handle_client(mut stream: TcpStream, config: &CasterConfig, caster: &Caster) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let request = read_request(&mut stream)?;
+
+    if request.mountpoint != config.mountpoint {
+        // v1 rovers get the sourcetable for unknown mountpoints too
+        if request.mountpoint.is_empty() || !request.v2 {
+            return send_sourcetable(&mut stream, config, request.v2);
+        }
+        return stream.write_all(
+            b"HTTP/1.1 404 Not Found\r\nNtrip-Version: Ntrip/2.0\r\nConnection: close\r\n\r\n",
+        );
+    }
+
+    if !is_authorized(config, &request) {
+        let response = if request.v2 {
+            "HTTP/1.1 401 Unauthorized\r\nNtrip-Version: Ntrip/2.0\r\n"
+        } else {
+            "HTTP/1.0 401 Unauthorized\r\n"
+        };
+        let response = format!(
+            "{}WWW-Authenticate: Basic realm=\"/{}\"\r\nConnection: close\r\n\r\n",
+            response, config.mountpoint
+        );
+        return stream.write_all(response.as_bytes());
+    }
+
+    if request.v2 {
+        stream.write_all(
+            b"HTTP/1.1 200 OK\r\nNtrip-Version: Ntrip/2.0\r\nServer: NTRIP MAARCO\r\n\
+            Content-Type: gnss/data\r\nTransfer-Encoding: chunked\r\n\
+            Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+        )?;
+    } else {
+        stream.write_all(b"ICY 200 OK\r\n")?;
+    }
+
+    // Rovers may send their position (GGA) upstream. We don't use it, but
+    // it has to be read or the socket buffer fills up and the rover stalls.
+    let mut upstream = stream.try_clone()?;
+    upstream.set_read_timeout(None)?;
+    thread::spawn(move || io::copy(&mut upstream, &mut io::sink()));
+
+    let result = stream_to_client(&mut stream, caster.add_client(), request.v2);
+    // Wakes up the thread reading upstream if the rover is still connected
+    let _ = stream.shutdown(Shutdown::Both);
+    result
+}
+
+fn stream_to_client(
+    stream: &mut TcpStream,
+    rx: Receiver<Arc<Vec<u8>>>,
+    chunked: bool,
+) -> io::Result<()> {
+    for data in rx {
+        if chunked {
+            stream.write_all(format!("{:x}\r\n", data.len()).as_bytes())?;
+            stream.write_all(&data)?;
+            stream.write_all(b"\r\n")?;
+        } else {
+            stream.write_all(&data)?;
+        }
+    }
+    Ok(())
+}
+
+/// Start listening for rovers. Corrections passed to `Caster::broadcast` on
+/// the returned handle are sent to every rover that requested our mountpoint.
+pub fn start_caster(config: CasterConfig) -> io::Result<Caster> {
+    let listener = TcpListener::bind((config.bind.as_str(), config.port))?;
+    println!(
+        "NTRIP caster listening on {}:{}/{}",
+        config.bind, config.port, config.mountpoint
+    );
+    Ok(serve(listener, config))
+}
+
+/// Holds one of the `max_clients` places until the rover's thread ends
+struct ClientSlot(Arc<AtomicUsize>);
+
+impl ClientSlot {
+    fn take(connected: &Arc<AtomicUsize>) -> Self {
+        connected.fetch_add(1, Ordering::SeqCst);
+        Self(connected.clone())
+    }
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Accept rovers on `listener` in the background
+fn serve(listener: TcpListener, config: CasterConfig) -> Caster {
+    let caster = Caster::default();
+    let accept_caster = caster.clone();
+    let config = Arc::new(config);
+    let connected = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("NTRIP caster accept error: {}", e);
+                    continue;
+                }
+            };
+            // Each rover gets a thread and a queue, so only so many at once
+            if connected.load(Ordering::SeqCst) >= config.max_clients {
+                let _ = stream
+                    .write_all(b"HTTP/1.0 503 Service Unavailable\r\nConnection: close\r\n\r\n");
+                continue;
+            }
+            let slot = ClientSlot::take(&connected);
+            let caster = accept_caster.clone();
+            let config = config.clone();
+            thread::spawn(move || {
+                let _slot = slot;
+                let peer = stream.peer_addr().map(|addr| addr.to_string());
+                if let Err(e) = handle_client(stream, &config, &caster) {
+                    eprintln!(
+                        "NTRIP caster client {} disconnected: {}",
+                        peer.unwrap_or_default(),
+                        e
+                    );
+                }
+            });
+        }
+    });
+
+    caster
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_read_request() {
+        let mut stream = Cursor::new(
+            b"GET /MAARCO HTTP/1.1\r\nHost: boat\r\nNtrip-Version: Ntrip/2.0\r\n\
+            Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+                .to_vec(),
+        );
+        let request = read_request(&mut stream).unwrap();
+        assert_eq!(request.mountpoint, "MAARCO");
+        assert!(request.v2);
+        assert_eq!(
+            request.credentials,
+            Some(("user".to_string(), "pass".to_string()))
+        );
+
+        let config = CasterConfig {
+            username: Some("user".to_string()),
+            password: Some("pass".to_string()),
+            ..CasterConfig::default()
+        };
+        assert!(is_authorized(&config, &request));
+
+        // A username on its own is enough when no password is set
+        let config = CasterConfig {
+            password: None,
+            ..config
+        };
+        assert!(is_authorized(&config, &request));
+
+        // And the other way round, but a password still needs a login
+        let config = CasterConfig {
+            username: None,
+            password: Some("pass".to_string()),
+            ..config
+        };
+        assert!(is_authorized(&config, &request));
+        let anonymous = ClientRequest {
+            credentials: None,
+            ..request
+        };
+        assert!(!is_authorized(&config, &anonymous));
+    }
+
+    /// Send `request` to the caster and read the status line of its answer
+    fn connect(addr: SocketAddr, request: &str) -> (TcpStream, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT)).unwrap();
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT)).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let status = read_header_line(&mut stream).unwrap();
+        (stream, status)
+    }
+
+    #[test]
+    fn test_serve_rovers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let caster = serve(
+            listener,
+            CasterConfig {
+                username: Some("boat".to_string()),
+                password: Some("secret".to_string()),
+                ..CasterConfig::default()
+            },
+        );
+
+        let (_, status) = connect(addr, "GET /MAARCO HTTP/1.0\r\n\r\n");
+        assert!(status.starts_with("HTTP/1.0 401"), "{}", status);
+        // "boat:wrong"
+        let (_, status) = connect(
+            addr,
+            "GET /MAARCO HTTP/1.0\r\nAuthorization: Basic Ym9hdDp3cm9uZw==\r\n\r\n",
+        );
+        assert!(status.starts_with("HTTP/1.0 401"), "{}", status);
+
+        // "boat:secret"
+        let (mut rover, status) = connect(
+            addr,
+            "GET /MAARCO HTTP/1.0\r\nAuthorization: Basic Ym9hdDpzZWNyZXQ=\r\n\r\n",
+        );
+        assert_eq!(status, "ICY 200 OK\r\n");
+        // More upstream data than fits in the socket buffers, which only
+        // gets through if the caster reads it
+        let gga = b"$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n";
+        for _ in 0..100_000 {
+            rover.write_all(gga).unwrap();
+        }
+
+        let start = std::time::Instant::now();
+        while caster.client_count() == 0 {
+            assert!(start.elapsed() < REQUEST_TIMEOUT);
+            thread::sleep(Duration::from_millis(10));
+        }
+        caster.broadcast(&[0xD3, 0x00, 0x01]);
+        let mut data = [0u8; 3];
+        rover.read_exact(&mut data).unwrap();
+        assert_eq!(data, [0xD3, 0x00, 0x01]);
+    }
+    #[test]
+    fn test_max_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _caster = serve(
+            listener,
+            CasterConfig {
+                max_clients: 1,
+                ..CasterConfig::default()
+            },
+        );
+
+        let (_rover, status) = connect(addr, "GET /MAARCO HTTP/1.0\r\n\r\n");
+        assert_eq!(status, "ICY 200 OK\r\n");
+        // The next one is turned away without its request being read
+        let mut refused = TcpStream::connect(addr).unwrap();
+        refused.set_read_timeout(Some(REQUEST_TIMEOUT)).unwrap();
+        let status = read_header_line(&mut refused).unwrap();
+        assert!(status.starts_with("HTTP/1.0 503"), "{}", status);
+    }
+}
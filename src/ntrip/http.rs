@@ -0,0 +1,223 @@
+// src/ntrip/http.rs
+use std::io::{self, ErrorKind, Read};
+
+/// Longest header line we accept. Real headers are well under this, so
+/// anything longer is garbage or an attempt to make us buffer forever.
+const MAX_HEADER_LINE: usize = 8 * 1024;
+
+/// Status line and headers of a caster response.
+///
+/// NTRIP v1 casters answer with `ICY 200 OK` (no headers) or
+/// `SOURCETABLE 200 OK`, v2 casters with a normal HTTP/1.1 response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NtripResponse {
+    /// "ICY", "SOURCETABLE", "HTTP/1.0" or "HTTP/1.1"
+    pub protocol: String,
+    pub status_code: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl NtripResponse {
+    /// Look up a header value, ignoring the case of the name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.header("Transfer-Encoding")
+            .is_some_and(|value| value.to_ascii_lowercase().contains("chunked"))
+    }
+}
+
+/// Read a single `\n` terminated line from the stream, one byte at a time so
+/// that none of the data after the line is consumed. Lines longer than
+/// `MAX_HEADER_LINE` are rejected.
+pub fn read_header_line<R: Read>(stream: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "Caster closed the connection during the response header",
+            ));
+        }
+        line.push(byte[0]);
+        if byte[0] == b'\n' {
+            return Ok(String::from_utf8_lossy(&line).into_owned());
+        }
+        if line.len() >= MAX_HEADER_LINE {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Header line is too long",
+            ));
+        }
+    }
+}
+
+/// Parse a status line such as `HTTP/1.1 200 OK` or `ICY 200 OK`
+pub fn parse_status_line(line: &str) -> Option<(String, u16, String)> {
+    let mut parts = line.trim().splitn(3, ' ');
+    let protocol = parts.next()?;
+    if !(protocol == "ICY" || protocol == "SOURCETABLE" || protocol.starts_with("HTTP/")) {
+        return None;
+    }
+    let status_code = parts.next()?.parse().ok()?;
+    let reason = parts.next().unwrap_or("").to_string();
+    Some((protocol.to_string(), status_code, reason))
+}
+
+/// Read the status line and (if present) headers of a caster response. The
+/// stream is left positioned at the start of the body.
+pub fn read_response<R: Read>(stream: &mut R) -> io::Result<NtripResponse> {
+    let status = read_header_line(stream)?;
+    let (protocol, status_code, reason) = parse_status_line(&status).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Unrecognized caster response: {}", status.trim()),
+        )
+    })?;
+
+    // ICY responses go straight to data, the others have headers first
+    let mut headers = Vec::new();
+    if protocol != "ICY" {
+        loop {
+            let line = read_header_line(stream)?;
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+    }
+
+    Ok(NtripResponse {
+        protocol,
+        status_code,
+        reason,
+        headers,
+    })
+}
+
+/// Progress through a `Transfer-Encoding: chunked` body
+#[derive(Debug, Default)]
+pub struct ChunkState {
+    /// Bytes left in the current chunk
+    remaining: usize,
+    /// Partially read chunk size line, kept across reads that time out
+    size_line: Vec<u8>,
+    /// The terminating zero-length chunk has been read
+    finished: bool,
+}
+
+/// Read decoded body bytes from a chunked stream. Returns `Ok(0)` once the
+/// last chunk has been read.
+pub fn read_chunked<R: Read>(
+    stream: &mut R,
+    state: &mut ChunkState,
+    buf: &mut [u8],
+) -> io::Result<usize> {
+    if state.finished || buf.is_empty() {
+        return Ok(0);
+    }
+
+    if state.remaining == 0 {
+        // The CRLF after the previous chunk's data shows up as an empty line
+        let size_line = loop {
+            let mut byte = [0u8; 1];
+            if stream.read(&mut byte)? == 0 {
+                return Ok(0);
+            }
+            if byte[0] != b'\n' {
+                state.size_line.push(byte[0]);
+                continue;
+            }
+            let line = String::from_utf8_lossy(&state.size_line).into_owned();
+            state.size_line.clear();
+            if !line.trim().is_empty() {
+                break line;
+            }
+        };
+        // Chunk extensions after ';' are allowed but meaningless to us
+        let size = size_line.trim().split(';').next().unwrap_or("");
+        let size = usize::from_str_radix(size.trim(), 16).map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid chunk size: {}", size_line.trim()),
+            )
+        })?;
+        if size == 0 {
+            state.finished = true;
+            return Ok(0);
+        }
+        state.remaining = size;
+    }
+
+    let len = buf.len().min(state.remaining);
+    let n = stream.read(&mut buf[..len])?;
+    state.remaining -= n;
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_v2_response() {
+        let mut stream = Cursor::new(
+            b"HTTP/1.1 200 OK\r\nNtrip-Version: Ntrip/2.0\r\n\
+            Transfer-Encoding: chunked\r\n\r\n\xd3"
+                .to_vec(),
+        );
+        let response = read_response(&mut stream).unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.header("ntrip-version"), Some("Ntrip/2.0"));
+        assert!(response.is_chunked());
+        // Body is left in the stream
+        assert_eq!(stream.position(), stream.get_ref().len() as u64 - 1);
+
+        let mut stream = Cursor::new(b"ICY 200 OK\r\n\xd3".to_vec());
+        let response = read_response(&mut stream).unwrap();
+        assert_eq!(response.protocol, "ICY");
+        assert!(response.headers.is_empty());
+    }
+
+    #[test]
+    fn test_header_line_too_long() {
+        let mut line = vec![b'A'; MAX_HEADER_LINE - 1];
+        line.push(b'\n');
+        assert_eq!(
+            read_header_line(&mut Cursor::new(&line)).unwrap().len(),
+            MAX_HEADER_LINE
+        );
+
+        let mut line = vec![b'A'; MAX_HEADER_LINE];
+        line.push(b'\n');
+        let err = read_header_line(&mut Cursor::new(&line)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_chunked() {
+        let mut stream = Cursor::new(b"3\r\nabc\r\n5;ext=1\r\ndefgh\r\n0\r\n\r\n".to_vec());
+        let mut state = ChunkState::default();
+        let mut body = Vec::new();
+        let mut buf = [0u8; 2];
+        loop {
+            let n = read_chunked(&mut stream, &mut state, &mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(body, b"abcdefgh");
+    }
+}
@@ -0,0 +1,194 @@
+// src/ntrip/sourcetable.rs
+
+/// A data stream (mountpoint) offered by the caster, from an `STR` record
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamRecord {
+    pub mountpoint: String,
+    pub identifier: String,
+    /// Data format, e.g. "RTCM 3.2"
+    pub format: String,
+    pub format_details: String,
+    pub carrier: u8,
+    /// E.g. "GPS+GLO+GAL+BDS"
+    pub nav_system: String,
+    pub network: String,
+    pub country: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// The caster expects the rover to send its position as GGA
+    pub nmea: bool,
+    /// Network (VRS) solution rather than a single base station
+    pub network_solution: bool,
+    pub generator: String,
+    pub authentication: String,
+    pub fee: bool,
+    pub bitrate: u32,
+}
+
+impl StreamRecord {
+    pub fn is_rtcm3(&self) -> bool {
+        let format = self.format.to_ascii_uppercase().replace(' ', "");
+        format.starts_with("RTCM3")
+    }
+}
+
+/// Another caster listed in the sourcetable, from a `CAS` record
+#[derive(Debug, Clone, PartialEq)]
+pub struct CasterRecord {
+    pub host: String,
+    pub port: u16,
+    pub identifier: String,
+    pub operator: String,
+    pub nmea: bool,
+    pub country: String,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// A network of stations, from a `NET` record
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkRecord {
+    pub identifier: String,
+    pub operator: String,
+    pub authentication: String,
+    pub fee: bool,
+    pub web_net: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sourcetable {
+    pub streams: Vec<StreamRecord>,
+    pub casters: Vec<CasterRecord>,
+    pub networks: Vec<NetworkRecord>,
+}
+
+fn field(fields: &[&str], index: usize) -> String {
+    fields
+        .get(index)
+        .map_or(String::new(), |f| f.trim().to_string())
+}
+
+fn number<T: std::str::FromStr + Default>(fields: &[&str], index: usize) -> T {
+    fields
+        .get(index)
+        .and_then(|f| f.trim().parse().ok())
+        .unwrap_or_default()
+}
+
+fn flag(fields: &[&str], index: usize) -> bool {
+    matches!(fields.get(index).map(|f| f.trim()), Some("1") | Some("Y"))
+}
+
+/// Parse the body of a sourcetable response. Unknown or malformed lines are
+/// skipped.
+pub fn parse(text: &str) -> Sourcetable {
+    let mut table = Sourcetable::default();
+
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split(';').collect();
+        match fields[0].trim() {
+            "STR" if fields.len() >= 11 => table.streams.push(StreamRecord {
+                mountpoint: field(&fields, 1),
+                identifier: field(&fields, 2),
+                format: field(&fields, 3),
+                format_details: field(&fields, 4),
+                carrier: number(&fields, 5),
+                nav_system: field(&fields, 6),
+                network: field(&fields, 7),
+                country: field(&fields, 8),
+                latitude: number(&fields, 9),
+                longitude: number(&fields, 10),
+                nmea: flag(&fields, 11),
+                network_solution: flag(&fields, 12),
+                generator: field(&fields, 13),
+                authentication: field(&fields, 15),
+                fee: flag(&fields, 16),
+                bitrate: number(&fields, 17),
+            }),
+            "CAS" if fields.len() >= 9 => table.casters.push(CasterRecord {
+                host: field(&fields, 1),
+                port: number(&fields, 2),
+                identifier: field(&fields, 3),
+                operator: field(&fields, 4),
+                nmea: flag(&fields, 5),
+                country: field(&fields, 6),
+                latitude: number(&fields, 7),
+                longitude: number(&fields, 8),
+            }),
+            "NET" if fields.len() >= 5 => table.networks.push(NetworkRecord {
+                identifier: field(&fields, 1),
+                operator: field(&fields, 2),
+                authentication: field(&fields, 3),
+                fee: flag(&fields, 4),
+                web_net: field(&fields, 5),
+            }),
+            _ => {}
+        }
+    }
+
+    table
+}
+
+/// Great-circle distance between two points in kilometres
+pub fn distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    const EARTH_RADIUS_KM: f64 = 6371.0;
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+impl Sourcetable {
+    /// The closest RTCM3 stream to the given position, along with its
+    /// distance in kilometres
+    pub fn nearest_rtcm3(&self, latitude: f64, longitude: f64) -> Option<(&StreamRecord, f64)> {
+        self.streams
+            .iter()
+            .filter(|stream| stream.is_rtcm3())
+            .map(|stream| {
+                let distance = distance_km(latitude, longitude, stream.latitude, stream.longitude);
+                (stream, distance)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = "\
+CAS;rtk2go.com;2101;RTK2GO;SNIP;0;USA;47.61;-122.33;0.0.0.0;0;http://rtk2go.com
+NET;SNIP;RTK2GO;B;N;http://rtk2go.com;none;none;none
+STR;RALEIGH;Raleigh;RTCM 3.2;1005(10),1077(1);2;GPS+GLO;SNIP;USA;35.78;-78.64;0;0;sNTRIP;none;B;N;9600;
+STR;DURHAM;Durham;RTCM 3.3;1005(10),1077(1);2;GPS;SNIP;USA;35.99;-78.90;1;0;sNTRIP;none;B;N;4800;
+STR;OLD;Cary;RTCM 2.3;1(1);1;GPS;SNIP;USA;35.76;-78.72;0;0;sNTRIP;none;B;N;2400;
+ENDSOURCETABLE
+";
+
+    #[test]
+    fn test_parse_sourcetable() {
+        let table = parse(TABLE);
+        assert_eq!(table.streams.len(), 3);
+        assert_eq!(table.casters.len(), 1);
+        assert_eq!(table.networks.len(), 1);
+
+        let durham = &table.streams[1];
+        assert_eq!(durham.mountpoint, "DURHAM");
+        assert_eq!(durham.format, "RTCM 3.3");
+        assert_eq!(durham.latitude, 35.99);
+        assert!(durham.nmea);
+        assert_eq!(durham.bitrate, 4800);
+        assert_eq!(table.casters[0].port, 2101);
+    }
+
+    #[test]
+    fn test_nearest_rtcm3() {
+        let table = parse(TABLE);
+        // Lake Johnson is closest to the RTCM 2 stream in Cary, which is skipped
+        let (stream, distance) = table.nearest_rtcm3(35.76, -78.71).unwrap();
+        assert_eq!(stream.mountpoint, "RALEIGH");
+        assert!(distance < 10.0);
+    }
+}
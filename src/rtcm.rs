@@ -0,0 +1,3 @@
+pub mod framer;
+pub mod messages;
+pub mod monitor;
@@ -0,0 +1,203 @@
+// src/rtcm/framer.rs
+
+/// Every RTCM3 frame starts with this byte
+pub const PREAMBLE: u8 = 0xD3;
+/// Preamble, 6 reserved bits and the 10 bit payload length
+const HEADER_LEN: usize = 3;
+const CRC_LEN: usize = 3;
+
+/// CRC-24Q as used by RTCM3 (polynomial 0x1864CFB, zero initial value)
+pub fn crc24q(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0;
+    for &byte in data {
+        crc ^= (byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x100_0000 != 0 {
+                crc ^= 0x186_4CFB;
+            }
+        }
+    }
+    crc & 0xFF_FFFF
+}
+
+/// A complete RTCM3 frame (header, payload and CRC) that passed the CRC check
+#[derive(Debug, Clone, PartialEq)]
+pub struct RtcmFrame {
+    data: Vec<u8>,
+}
+
+impl RtcmFrame {
+    /// The whole frame as it should be forwarded to the receiver
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data[HEADER_LEN..self.data.len() - CRC_LEN]
+    }
+
+    /// Message number from the first 12 bits of the payload (e.g. 1005)
+    pub fn message_type(&self) -> Option<u16> {
+        let payload = self.payload();
+        if payload.len() < 2 {
+            return None;
+        }
+        Some(((payload[0] as u16) << 4) | ((payload[1] as u16) >> 4))
+    }
+}
+
+/// Result of looking for a frame at the start of a buffer
+#[derive(Debug, PartialEq)]
+pub enum RtcmParse {
+    Complete(RtcmFrame),
+    /// Looks like the start of a frame, more data is needed
+    Incomplete,
+    /// Not a frame, or the CRC didn't match
+    Invalid,
+}
+
+/// Try to parse an RTCM3 frame at the start of `buf`
+pub fn parse_frame(buf: &[u8]) -> RtcmParse {
+    match buf.first() {
+        Some(&PREAMBLE) => {}
+        Some(_) => return RtcmParse::Invalid,
+        None => return RtcmParse::Incomplete,
+    }
+    if buf.len() < HEADER_LEN {
+        return RtcmParse::Incomplete;
+    }
+    // The 6 bits after the preamble are reserved and always zero, so
+    // anything else means this 0xD3 was just part of the data
+    if buf[1] & 0xFC != 0 {
+        return RtcmParse::Invalid;
+    }
+
+    let payload_len = (((buf[1] & 0x03) as usize) << 8) | buf[2] as usize;
+    let frame_len = HEADER_LEN + payload_len + CRC_LEN;
+    if buf.len() < frame_len {
+        return RtcmParse::Incomplete;
+    }
+
+    let body = &buf[..HEADER_LEN + payload_len];
+    let crc = &buf[HEADER_LEN + payload_len..frame_len];
+    let expected = ((crc[0] as u32) << 16) | ((crc[1] as u32) << 8) | crc[2] as u32;
+    if crc24q(body) != expected {
+        return RtcmParse::Invalid;
+    }
+    RtcmParse::Complete(RtcmFrame {
+        data: buf[..frame_len].to_vec(),
+    })
+}
+
+/// Reassembles RTCM3 frames from a byte stream that may split or join them
+/// arbitrarily, e.g. TCP reads from a caster.
+#[derive(Debug, Default)]
+pub struct RtcmFramer {
+    buffer: Vec<u8>,
+    /// Frames whose header looked valid but whose CRC didn't match
+    pub corrupt_frames: u64,
+    /// Bytes skipped while searching for a preamble
+    pub discarded_bytes: u64,
+}
+
+impl RtcmFramer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add newly received bytes and return every frame they complete
+    pub fn push(&mut self, data: &[u8]) -> Vec<RtcmFrame> {
+        self.buffer.extend_from_slice(data);
+        let mut frames = Vec::new();
+
+        loop {
+            // Drop anything before the next preamble
+            match self.buffer.iter().position(|&b| b == PREAMBLE) {
+                Some(start) => self.skip(start),
+                None => {
+                    self.skip(self.buffer.len());
+                    break;
+                }
+            }
+
+            match parse_frame(&self.buffer) {
+                RtcmParse::Complete(frame) => {
+                    self.buffer.drain(..frame.as_bytes().len());
+                    frames.push(frame);
+                }
+                RtcmParse::Incomplete => break,
+                RtcmParse::Invalid => {
+                    // Only count frames whose header looked plausible
+                    if self.buffer[1] & 0xFC == 0 {
+                        self.corrupt_frames += 1;
+                    }
+                    // Resynchronize from the byte after this false preamble
+                    self.skip(1);
+                }
+            }
+        }
+
+        frames
+    }
+
+    fn skip(&mut self, count: usize) {
+        self.buffer.drain(..count);
+        self.discarded_bytes += count as u64;
+    }
+}
+
+/// Build a valid frame around `payload`, for tests that need RTCM3 data
+#[cfg(test)]
+pub fn build_frame(payload: &[u8]) -> Vec<u8> {
+    let mut data = vec![PREAMBLE, (payload.len() >> 8) as u8, payload.len() as u8];
+    data.extend_from_slice(payload);
+    let crc = crc24q(&data);
+    data.extend_from_slice(&[(crc >> 16) as u8, (crc >> 8) as u8, crc as u8]);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc24q() {
+        assert_eq!(crc24q(b"123456789"), 0xCDE703);
+    }
+
+    #[test]
+    fn test_frames_split_and_joined() {
+        // Message 1005 and 1077 headers, back to back and split mid-frame
+        let mut stream = vec![0x00, 0x42];
+        stream.extend(build_frame(&[0x3E, 0xD0, 0x00, 0x01, 0x02]));
+        stream.extend(build_frame(&[0x43, 0x50, 0xAA]));
+
+        let mut framer = RtcmFramer::new();
+        let mut frames = framer.push(&stream[..6]);
+        assert!(frames.is_empty());
+        frames.extend(framer.push(&stream[6..]));
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].message_type(), Some(1005));
+        assert_eq!(frames[1].message_type(), Some(1077));
+        assert_eq!(frames[1].payload(), &[0x43, 0x50, 0xAA]);
+        assert_eq!(framer.discarded_bytes, 2);
+    }
+
+    #[test]
+    fn test_corrupt_frame_is_dropped() {
+        let mut bad = build_frame(&[0x3E, 0xD0, 0x00]);
+        bad[4] ^= 0xFF;
+        let good = build_frame(&[0x43, 0x50]);
+
+        let mut framer = RtcmFramer::new();
+        let mut stream = bad;
+        stream.extend(&good);
+        let frames = framer.push(&stream);
+
+        assert_eq!(framer.corrupt_frames, 1);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].as_bytes(), good.as_slice());
+    }
+}
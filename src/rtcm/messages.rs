@@ -0,0 +1,416 @@
+// src/rtcm/messages.rs
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::framer::RtcmFrame;
+
+/// Reads big-endian bit fields out of an RTCM payload
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Read an unsigned field of up to 64 bits
+    fn unsigned(&mut self, bits: usize) -> Option<u64> {
+        if self.pos + bits > self.data.len() * 8 {
+            return None;
+        }
+        let mut value = 0u64;
+        for _ in 0..bits {
+            let byte = self.data[self.pos / 8];
+            let bit = (byte >> (7 - self.pos % 8)) & 1;
+            value = (value << 1) | bit as u64;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+
+    /// Read a two's complement signed field
+    fn signed(&mut self, bits: usize) -> Option<i64> {
+        let value = self.unsigned(bits)?;
+        let shift = 64 - bits;
+        Some(((value << shift) as i64) >> shift)
+    }
+
+    fn flag(&mut self) -> Option<bool> {
+        Some(self.unsigned(1)? == 1)
+    }
+
+    /// Read a length-prefixed (8 bit) ASCII string
+    fn string(&mut self) -> Option<String> {
+        let len = self.unsigned(8)? as usize;
+        let bytes = (0..len)
+            .map(|_| self.unsigned(8).map(|b| b as u8))
+            .collect::<Option<Vec<u8>>>()?;
+        Some(String::from_utf8_lossy(&bytes).trim().to_string())
+    }
+}
+
+/// Reference station antenna position, from message 1005 or 1006
+#[derive(Debug, Clone, PartialEq)]
+pub struct StationPosition {
+    pub station_id: u16,
+    pub itrf_year: u8,
+    pub gps: bool,
+    pub glonass: bool,
+    pub galileo: bool,
+    /// ECEF coordinates of the antenna reference point in metres
+    pub ecef_x: f64,
+    pub ecef_y: f64,
+    pub ecef_z: f64,
+    /// Antenna height above the marker in metres (1006 only)
+    pub antenna_height: Option<f64>,
+}
+
+/// Antenna and receiver descriptors, from message 1033
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceiverDescriptor {
+    pub station_id: u16,
+    pub antenna: String,
+    pub antenna_setup_id: u8,
+    pub antenna_serial: String,
+    pub receiver: String,
+    pub firmware: String,
+    pub receiver_serial: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Constellation {
+    Gps,
+    Glonass,
+    Galileo,
+    Sbas,
+    Qzss,
+    BeiDou,
+}
+
+impl fmt::Display for Constellation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Constellation::Gps => "GPS",
+            Constellation::Glonass => "GLONASS",
+            Constellation::Galileo => "Galileo",
+            Constellation::Sbas => "SBAS",
+            Constellation::Qzss => "QZSS",
+            Constellation::BeiDou => "BeiDou",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Header of a Multiple Signal Message (MSM1-7, e.g. 1074 or 1077)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsmHeader {
+    pub constellation: Constellation,
+    /// 1 to 7, e.g. 4 for MSM4
+    pub msm_type: u8,
+    pub station_id: u16,
+    /// Constellation specific epoch time, in milliseconds for most systems
+    pub epoch_time: u32,
+    /// More MSMs follow for the same epoch
+    pub multiple_message: bool,
+    pub num_satellites: u8,
+    pub num_signals: u8,
+    /// Number of satellite/signal combinations with data
+    pub num_cells: u16,
+}
+
+/// Decoded contents of the RTCM3 messages we care about
+#[derive(Debug, Clone, PartialEq)]
+pub enum RtcmMessage {
+    StationPosition(StationPosition),
+    ReceiverDescriptor(ReceiverDescriptor),
+    Msm(MsmHeader),
+    /// Any other message, by number
+    Other(u16),
+}
+
+fn decode_station_position(bits: &mut BitReader, with_height: bool) -> Option<StationPosition> {
+    let station_id = bits.unsigned(12)? as u16;
+    let itrf_year = bits.unsigned(6)? as u8;
+    let gps = bits.flag()?;
+    let glonass = bits.flag()?;
+    let galileo = bits.flag()?;
+    let _reference_station = bits.flag()?;
+    let ecef_x = bits.signed(38)? as f64 * 0.0001;
+    let _single_receiver_oscillator = bits.flag()?;
+    let _reserved = bits.flag()?;
+    let ecef_y = bits.signed(38)? as f64 * 0.0001;
+    let _quarter_cycle = bits.unsigned(2)?;
+    let ecef_z = bits.signed(38)? as f64 * 0.0001;
+    let antenna_height = if with_height {
+        Some(bits.unsigned(16)? as f64 * 0.0001)
+    } else {
+        None
+    };
+
+    Some(StationPosition {
+        station_id,
+        itrf_year,
+        gps,
+        glonass,
+        galileo,
+        ecef_x,
+        ecef_y,
+        ecef_z,
+        antenna_height,
+    })
+}
+
+fn decode_receiver_descriptor(bits: &mut BitReader) -> Option<ReceiverDescriptor> {
+    let station_id = bits.unsigned(12)? as u16;
+    let antenna = bits.string()?;
+    let antenna_setup_id = bits.unsigned(8)? as u8;
+    let antenna_serial = bits.string()?;
+    let receiver = bits.string()?;
+    let firmware = bits.string()?;
+    let receiver_serial = bits.string()?;
+
+    Some(ReceiverDescriptor {
+        station_id,
+        antenna,
+        antenna_setup_id,
+        antenna_serial,
+        receiver,
+        firmware,
+        receiver_serial,
+    })
+}
+
+fn decode_msm_header(bits: &mut BitReader, message_type: u16) -> Option<MsmHeader> {
+    let constellation = match message_type / 10 {
+        107 => Constellation::Gps,
+        108 => Constellation::Glonass,
+        109 => Constellation::Galileo,
+        110 => Constellation::Sbas,
+        111 => Constellation::Qzss,
+        112 => Constellation::BeiDou,
+        _ => return None,
+    };
+    let msm_type = (message_type % 10) as u8;
+
+    let station_id = bits.unsigned(12)? as u16;
+    let epoch_time = bits.unsigned(30)? as u32;
+    let multiple_message = bits.flag()?;
+    // IODS, reserved, clock steering, external clock, smoothing flag and interval
+    bits.unsigned(3 + 7 + 2 + 2 + 1 + 3)?;
+    let satellite_mask = bits.unsigned(64)?;
+    let signal_mask = bits.unsigned(32)?;
+    let num_satellites = satellite_mask.count_ones() as u8;
+    let num_signals = signal_mask.count_ones() as u8;
+
+    let mut num_cells = 0;
+    for _ in 0..(num_satellites as usize * num_signals as usize) {
+        num_cells += bits.unsigned(1)? as u16;
+    }
+
+    Some(MsmHeader {
+        constellation,
+        msm_type,
+        station_id,
+        epoch_time,
+        multiple_message,
+        num_satellites,
+        num_signals,
+        num_cells,
+    })
+}
+
+/// Decode a frame's payload. Returns `None` if the payload is too short for
+/// its message type.
+pub fn decode(frame: &RtcmFrame) -> Option<RtcmMessage> {
+    let mut bits = BitReader::new(frame.payload());
+    let message_type = bits.unsigned(12)? as u16;
+
+    let message = match message_type {
+        1005 => RtcmMessage::StationPosition(decode_station_position(&mut bits, false)?),
+        1006 => RtcmMessage::StationPosition(decode_station_position(&mut bits, true)?),
+        1033 => RtcmMessage::ReceiverDescriptor(decode_receiver_descriptor(&mut bits)?),
+        1071..=1077 | 1081..=1087 | 1091..=1097 | 1101..=1107 | 1111..=1117 | 1121..=1127 => {
+            RtcmMessage::Msm(decode_msm_header(&mut bits, message_type)?)
+        }
+        other => RtcmMessage::Other(other),
+    };
+    Some(message)
+}
+
+/// Convert WGS84 latitude/longitude (degrees) and ellipsoidal height (metres)
+/// to ECEF coordinates
+pub fn geodetic_to_ecef(latitude: f64, longitude: f64, height: f64) -> (f64, f64, f64) {
+    const A: f64 = 6_378_137.0;
+    const F: f64 = 1.0 / 298.257_223_563;
+    let e2 = F * (2.0 - F);
+
+    let (lat, lon) = (latitude.to_radians(), longitude.to_radians());
+    let n = A / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+    (
+        (n + height) * lat.cos() * lon.cos(),
+        (n + height) * lat.cos() * lon.sin(),
+        (n * (1.0 - e2) + height) * lat.sin(),
+    )
+}
+
+impl StationPosition {
+    /// Straight-line distance in metres from the given rover position
+    /// (ellipsoidal height) to the base station antenna
+    pub fn baseline_m(&self, latitude: f64, longitude: f64, height: f64) -> f64 {
+        let (x, y, z) = geodetic_to_ecef(latitude, longitude, height);
+        ((x - self.ecef_x).powi(2) + (y - self.ecef_y).powi(2) + (z - self.ecef_z).powi(2)).sqrt()
+    }
+}
+
+/// Everything we know about the base station sending corrections
+#[derive(Debug, Clone, Default)]
+pub struct BaseStation {
+    pub position: Option<StationPosition>,
+    pub descriptor: Option<ReceiverDescriptor>,
+    /// Satellites in the latest MSM for each constellation
+    pub satellites: BTreeMap<Constellation, u8>,
+}
+
+impl BaseStation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, message: &RtcmMessage) {
+        match message {
+            RtcmMessage::StationPosition(position) => self.position = Some(position.clone()),
+            RtcmMessage::ReceiverDescriptor(descriptor) => {
+                self.descriptor = Some(descriptor.clone())
+            }
+            RtcmMessage::Msm(header) => {
+                self.satellites
+                    .insert(header.constellation, header.num_satellites);
+            }
+            RtcmMessage::Other(_) => {}
+        }
+    }
+
+    /// Distance in metres from the rover to the base antenna, once a 1005 or
+    /// 1006 message has been received
+    pub fn baseline_m(&self, latitude: f64, longitude: f64, height: f64) -> Option<f64> {
+        self.position
+            .as_ref()
+            .map(|position| position.baseline_m(latitude, longitude, height))
+    }
+
+    /// Satellite counts formatted like "GPS:9 GLONASS:6"
+    pub fn satellite_summary(&self) -> String {
+        self.satellites
+            .iter()
+            .map(|(constellation, count)| format!("{}:{}", constellation, count))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtcm::framer::{RtcmFramer, build_frame};
+
+    /// Packs big-endian bit fields, the inverse of `BitReader`
+    #[derive(Default)]
+    struct BitWriter {
+        bits: Vec<bool>,
+    }
+
+    impl BitWriter {
+        fn push(&mut self, value: i64, bits: usize) -> &mut Self {
+            for i in (0..bits).rev() {
+                self.bits.push((value >> i) & 1 == 1);
+            }
+            self
+        }
+
+        /// Wrap the packed payload in a valid RTCM3 frame
+        fn frame(&self) -> RtcmFrame {
+            let payload: Vec<u8> = self
+                .bits
+                .chunks(8)
+                .map(|chunk| {
+                    chunk
+                        .iter()
+                        .enumerate()
+                        .fold(0u8, |byte, (i, &bit)| byte | ((bit as u8) << (7 - i)))
+                })
+                .collect();
+            RtcmFramer::new().push(&build_frame(&payload)).remove(0)
+        }
+    }
+
+    #[test]
+    fn test_bit_reader_signed() {
+        let mut bits = BitReader::new(&[0xFF, 0xF0]);
+        assert_eq!(bits.signed(12), Some(-1));
+        assert_eq!(bits.unsigned(4), Some(0));
+        assert_eq!(bits.unsigned(1), None);
+    }
+
+    #[test]
+    fn test_decode_1006() {
+        // Base station on the shore of Lake Johnson
+        let (x, y, z) = geodetic_to_ecef(35.7627, -78.7153, 100.0);
+        let mut writer = BitWriter::default();
+        writer
+            .push(1006, 12)
+            .push(42, 12)
+            .push(0, 6)
+            .push(1, 1)
+            .push(1, 1)
+            .push(0, 1)
+            .push(0, 1)
+            .push((x / 0.0001).round() as i64, 38)
+            .push(0, 2)
+            .push((y / 0.0001).round() as i64, 38)
+            .push(0, 2)
+            .push((z / 0.0001).round() as i64, 38)
+            .push(15_000, 16);
+
+        let Some(RtcmMessage::StationPosition(position)) = decode(&writer.frame()) else {
+            panic!("expected a station position");
+        };
+        assert_eq!(position.station_id, 42);
+        assert!(position.gps && position.glonass && !position.galileo);
+        assert!((position.ecef_y - y).abs() < 0.001);
+        assert_eq!(position.antenna_height, Some(1.5));
+
+        // Rover 10 m straight above the base
+        let baseline = position.baseline_m(35.7627, -78.7153, 110.0);
+        assert!((baseline - 10.0).abs() < 0.01, "baseline was {}", baseline);
+    }
+
+    #[test]
+    fn test_decode_msm_header() {
+        let mut writer = BitWriter::default();
+        writer
+            .push(1077, 12)
+            .push(42, 12)
+            .push(123_456, 30)
+            .push(1, 1)
+            .push(0, 18)
+            // Satellites 1, 2 and 3 on signals 2 and 3
+            .push(0b111 << 61, 64)
+            .push(0b11 << 29, 32)
+            .push(0b110111, 6);
+
+        let Some(RtcmMessage::Msm(header)) = decode(&writer.frame()) else {
+            panic!("expected an MSM header");
+        };
+        assert_eq!(header.constellation, Constellation::Gps);
+        assert_eq!(header.msm_type, 7);
+        assert_eq!(header.epoch_time, 123_456);
+        assert!(header.multiple_message);
+        assert_eq!(header.num_satellites, 3);
+        assert_eq!(header.num_signals, 2);
+        assert_eq!(header.num_cells, 5);
+    }
+}
@@ -0,0 +1,150 @@
+// src/rtcm/monitor.rs
+use std::time::{Duration, Instant};
+
+/// How often the throughput figures are recomputed
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Keeps track of how fresh our corrections are, so we notice when they stop
+/// arriving instead of silently dropping out of RTK fixed.
+#[derive(Debug)]
+pub struct CorrectionMonitor {
+    /// Corrections older than this are considered stale
+    stale_after: Duration,
+    started: Instant,
+    last_frame: Option<Instant>,
+    /// "Age of differential" reported by the rover in its last GGA
+    differential_age: Option<f32>,
+    stale: bool,
+    window_start: Instant,
+    window_bytes: usize,
+    window_messages: usize,
+    pub bytes_per_sec: f64,
+    pub messages_per_sec: f64,
+    pub total_messages: u64,
+}
+
+impl CorrectionMonitor {
+    pub fn new(stale_after: Duration, now: Instant) -> Self {
+        Self {
+            stale_after,
+            started: now,
+            last_frame: None,
+            differential_age: None,
+            stale: false,
+            window_start: now,
+            window_bytes: 0,
+            window_messages: 0,
+            bytes_per_sec: 0.0,
+            messages_per_sec: 0.0,
+            total_messages: 0,
+        }
+    }
+
+    /// Record a valid RTCM frame of `len` bytes
+    pub fn record_frame(&mut self, len: usize, now: Instant) {
+        self.last_frame = Some(now);
+        self.window_bytes += len;
+        self.window_messages += 1;
+        self.total_messages += 1;
+    }
+
+    /// Record the age of differential from the rover's GGA. `None` means the
+    /// rover isn't using corrections at all.
+    pub fn record_differential_age(&mut self, age: Option<f32>) {
+        self.differential_age = age;
+    }
+
+    pub fn differential_age(&self) -> Option<f32> {
+        self.differential_age
+    }
+
+    /// Time since the last valid frame, or since startup if none has arrived
+    pub fn correction_age(&self, now: Instant) -> Duration {
+        now.duration_since(self.last_frame.unwrap_or(self.started))
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Describe why corrections are (or aren't) stale, for logs and display
+    pub fn summary(&self, now: Instant) -> String {
+        let age = match self.last_frame {
+            Some(_) => format!(
+                "last RTCM {:.1} s ago",
+                self.correction_age(now).as_secs_f64()
+            ),
+            None => "no RTCM received yet".to_string(),
+        };
+        match self.differential_age {
+            Some(differential_age) => {
+                format!("{}, differential age {:.1} s", age, differential_age)
+            }
+            None => age,
+        }
+    }
+
+    /// Refresh the throughput figures and the stale flag. Returns the new
+    /// stale state if it changed.
+    pub fn update(&mut self, now: Instant) -> Option<bool> {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= RATE_WINDOW {
+            let secs = elapsed.as_secs_f64();
+            self.bytes_per_sec = self.window_bytes as f64 / secs;
+            self.messages_per_sec = self.window_messages as f64 / secs;
+            self.window_start = now;
+            self.window_bytes = 0;
+            self.window_messages = 0;
+        }
+
+        // The rover's own idea of the age catches corrections that arrive
+        // but that it can't use, e.g. from a base that's too far away
+        let differential_stale = self
+            .differential_age
+            .is_some_and(|age| age as f64 > self.stale_after.as_secs_f64());
+        let stale = self.correction_age(now) > self.stale_after || differential_stale;
+        if stale == self.stale {
+            return None;
+        }
+        self.stale = stale;
+        Some(stale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_transitions() {
+        let start = Instant::now();
+        let mut monitor = CorrectionMonitor::new(Duration::from_secs(5), start);
+        assert_eq!(monitor.update(start + Duration::from_secs(1)), None);
+
+        // Nothing arrived since startup
+        assert_eq!(monitor.update(start + Duration::from_secs(6)), Some(true));
+        assert_eq!(monitor.update(start + Duration::from_secs(7)), None);
+
+        monitor.record_frame(100, start + Duration::from_secs(8));
+        assert_eq!(monitor.update(start + Duration::from_secs(8)), Some(false));
+
+        // Frames keep coming but the rover says they're too old to use
+        monitor.record_differential_age(Some(9.0));
+        assert_eq!(monitor.update(start + Duration::from_secs(9)), Some(true));
+        monitor.record_differential_age(Some(1.0));
+        assert_eq!(monitor.update(start + Duration::from_secs(9)), Some(false));
+    }
+
+    #[test]
+    fn test_rates() {
+        let start = Instant::now();
+        let mut monitor = CorrectionMonitor::new(Duration::from_secs(5), start);
+        for i in 0..4 {
+            monitor.record_frame(50, start + Duration::from_millis(i * 400));
+        }
+        monitor.update(start + Duration::from_secs(2));
+        assert_eq!(monitor.bytes_per_sec, 100.0);
+        assert_eq!(monitor.messages_per_sec, 2.0);
+        assert_eq!(monitor.total_messages, 4);
+    }
+}
@@ -0,0 +1,102 @@
+// src/serial_link.rs
+//! Keeps a serial device open across cable wiggles: a dead handle is dropped
+//! and the device is reopened every few seconds until it comes back.
+use serialport::{Error, ErrorKind};
+use std::io;
+use std::time::{Duration, Instant};
+
+/// How often to try reopening a device that went away
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    Connected,
+    Disconnected,
+}
+
+/// Whether a read or write error means the device is gone, as opposed to a
+/// timeout or a bad line of data
+pub fn is_disconnect(error: &Error) -> bool {
+    match error.kind() {
+        ErrorKind::NoDevice => true,
+        ErrorKind::Io(kind) => !matches!(
+            kind,
+            io::ErrorKind::TimedOut
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::Interrupted
+                | io::ErrorKind::InvalidData
+        ),
+        ErrorKind::InvalidInput | ErrorKind::Unknown => false,
+    }
+}
+
+/// A serial device that may come and go
+#[derive(Debug)]
+pub struct SerialLink<T> {
+    port: Option<T>,
+    last_attempt: Instant,
+    /// Number of times the device has been reopened
+    pub reconnects: u32,
+}
+
+impl<T> SerialLink<T> {
+    pub fn new(port: Option<T>) -> Self {
+        Self {
+            port,
+            last_attempt: Instant::now(),
+            reconnects: 0,
+        }
+    }
+
+    pub fn state(&self) -> LinkState {
+        if self.port.is_some() {
+            LinkState::Connected
+        } else {
+            LinkState::Disconnected
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.port.as_ref()
+    }
+
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        self.port.as_mut()
+    }
+
+    /// Close the dead handle. We'll wait a full retry interval before
+    /// reopening, since the device usually takes a moment to re-enumerate.
+    pub fn disconnect(&mut self) {
+        self.port = None;
+        self.last_attempt = Instant::now();
+    }
+
+    /// If disconnected and it's time to retry, try `open` again. Returns the
+    /// newly opened port, so the caller can set it up before using it.
+    pub fn reopen(&mut self, open: impl FnOnce() -> Result<T, Error>) -> Option<&mut T> {
+        if self.port.is_some() || self.last_attempt.elapsed() < RETRY_INTERVAL {
+            return None;
+        }
+        self.last_attempt = Instant::now();
+        let port = open().ok()?;
+        self.reconnects += 1;
+        self.port = Some(port);
+        self.port.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_disconnect() {
+        let timeout = Error::new(ErrorKind::Io(io::ErrorKind::TimedOut), "timed out");
+        assert!(!is_disconnect(&timeout));
+        let bad_line = Error::new(ErrorKind::Io(io::ErrorKind::InvalidData), "bad line");
+        assert!(!is_disconnect(&bad_line));
+        let unplugged = Error::new(ErrorKind::Io(io::ErrorKind::BrokenPipe), "unplugged");
+        assert!(is_disconnect(&unplugged));
+        assert!(is_disconnect(&Error::new(ErrorKind::NoDevice, "gone")));
+    }
+}
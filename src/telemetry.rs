@@ -0,0 +1,331 @@
+// src/telemetry.rs
+//! Sensor data from the Arduino. The firmware starts with a header line
+//! naming its fields and units, e.g.
+//!
+//! ```text
+//! #motor_1_rpm:rpm,motor_2_rpm:rpm,time_ms:ms,sonar_mm:mm
+//! ```
+//!
+//! and then sends the values as comma separated lines in that order. Adding a
+//! sensor only needs a firmware change; records carry their schema through
+//! logging and display.
+//!
+//! Newer firmware can send the same header and values in binary frames
+//! instead, see [`binary`].
+pub mod binary;
+
+use std::fmt;
+use std::sync::Arc;
+
+/// Why a line from the Arduino couldn't be used
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelemetryError {
+    FieldCount { expected: usize, found: usize },
+    BadNumber { field: String, value: String },
+    OutOfRange { field: String, value: f64 },
+    BadHeader(String),
+}
+
+impl fmt::Display for TelemetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TelemetryError::FieldCount { expected, found } => {
+                write!(f, "Expected {} fields, got {}", expected, found)
+            }
+            TelemetryError::BadNumber { field, value } => {
+                write!(f, "Bad number for {}: {:?}", field, value)
+            }
+            TelemetryError::OutOfRange { field, value } => {
+                write!(f, "{} out of range: {}", field, value)
+            }
+            TelemetryError::BadHeader(reason) => write!(f, "Bad header: {}", reason),
+        }
+    }
+}
+
+/// One field of a telemetry line, e.g. `sonar_mm` in `mm`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldSpec {
+    pub name: String,
+    pub unit: Option<String>,
+}
+
+impl FieldSpec {
+    fn new(name: &str, unit: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            unit: Some(unit.to_string()),
+        }
+    }
+
+    /// Limits well outside anything a sensor with this unit can report.
+    /// Values beyond them are line noise.
+    fn plausible_range(&self) -> (f64, f64) {
+        match self.unit.as_deref() {
+            Some("rpm") => (-20_000.0, 20_000.0),
+            Some("rev") => (-1e9, 1e9),
+            Some("ms") => (0.0, u32::MAX as f64),
+            Some("mA") => (-50_000.0, 50_000.0),
+            Some("mm") => (0.0, 10_000.0),
+            _ => (f64::MIN, f64::MAX),
+        }
+    }
+}
+
+impl fmt::Display for FieldSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.unit {
+            Some(unit) => write!(f, "{} [{}]", self.name, unit),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// The fields the Arduino sends, in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    pub fields: Vec<FieldSpec>,
+}
+
+impl Schema {
+    /// What firmware from before the header line sends
+    pub fn legacy() -> Self {
+        Self {
+            fields: vec![
+                FieldSpec::new("motor_1_rpm", "rpm"),
+                FieldSpec::new("motor_2_rpm", "rpm"),
+                FieldSpec::new("motor_1_tot_rotations", "rev"),
+                FieldSpec::new("motor_2_tot_rotations", "rev"),
+                FieldSpec::new("time_ms", "ms"),
+                FieldSpec::new("current_motor_1_ma", "mA"),
+                FieldSpec::new("current_motor_2_ma", "mA"),
+                FieldSpec::new("sonar_mm", "mm"),
+                FieldSpec::new("tof1_mm", "mm"),
+                FieldSpec::new("tof2_mm", "mm"),
+            ],
+        }
+    }
+
+    pub fn is_header(line: &str) -> bool {
+        line.trim_start().starts_with('#')
+    }
+
+    /// Parse a `#name:unit,name,...` header line. The unit is optional.
+    pub fn parse_header(line: &str) -> Result<Self, TelemetryError> {
+        let Some(body) = line.trim().strip_prefix('#') else {
+            return Err(TelemetryError::BadHeader("missing #".to_string()));
+        };
+        let mut fields: Vec<FieldSpec> = Vec::new();
+        for part in body.split(',') {
+            let (name, unit) = match part.split_once(':') {
+                Some((name, unit)) => (name.trim(), Some(unit.trim())),
+                None => (part.trim(), None),
+            };
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(TelemetryError::BadHeader(format!(
+                    "bad field name {:?}",
+                    name
+                )));
+            }
+            if fields.iter().any(|field| field.name == name) {
+                return Err(TelemetryError::BadHeader(format!(
+                    "duplicate field {}",
+                    name
+                )));
+            }
+            fields.push(FieldSpec {
+                name: name.to_string(),
+                unit: unit.filter(|unit| !unit.is_empty()).map(String::from),
+            });
+        }
+        Ok(Self { fields })
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|field| field.name == name)
+    }
+
+    /// Parse a line of comma separated values in this schema's order
+    pub fn parse_record(self: &Arc<Self>, line: &str) -> Result<TelemetryRecord, TelemetryError> {
+        let parts: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
+        if parts.len() != self.fields.len() {
+            return Err(TelemetryError::FieldCount {
+                expected: self.fields.len(),
+                found: parts.len(),
+            });
+        }
+
+        let values = parts
+            .iter()
+            .zip(&self.fields)
+            .map(|(part, field)| {
+                part.parse().map_err(|_| TelemetryError::BadNumber {
+                    field: field.name.clone(),
+                    value: part.to_string(),
+                })
+            })
+            .collect::<Result<Vec<f64>, _>>()?;
+        self.record(values)
+    }
+
+    /// Check values received in this schema's order and make a record of them
+    pub fn record(self: &Arc<Self>, values: Vec<f64>) -> Result<TelemetryRecord, TelemetryError> {
+        if values.len() != self.fields.len() {
+            return Err(TelemetryError::FieldCount {
+                expected: self.fields.len(),
+                found: values.len(),
+            });
+        }
+        for (&value, field) in values.iter().zip(&self.fields) {
+            let (min, max) = field.plausible_range();
+            // Also catches NaN and infinity, which the Arduino prints as
+            // "nan" and "inf"
+            if !(min..=max).contains(&value) {
+                return Err(TelemetryError::OutOfRange {
+                    field: field.name.clone(),
+                    value,
+                });
+            }
+        }
+
+        Ok(TelemetryRecord {
+            schema: Arc::clone(self),
+            values,
+        })
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<String> = self.fields.iter().map(FieldSpec::to_string).collect();
+        write!(f, "{}", fields.join(", "))
+    }
+}
+
+/// One line of sensor values, along with the schema that names them
+#[derive(Debug, Clone, PartialEq)]
+pub struct TelemetryRecord {
+    pub schema: Arc<Schema>,
+    pub values: Vec<f64>,
+}
+
+impl TelemetryRecord {
+    /// The value of a field by name, e.g. `record.get("motor_1_rpm")`
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.schema.index_of(name).map(|i| self.values[i])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&FieldSpec, f64)> {
+        self.schema.fields.iter().zip(self.values.iter().copied())
+    }
+}
+
+/// Good and bad lines from the Arduino
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TelemetryStats {
+    pub ok: u64,
+    pub field_count_errors: u64,
+    pub number_errors: u64,
+    pub range_errors: u64,
+    pub header_errors: u64,
+    /// Binary frames with a bad CRC or encoding
+    pub frame_errors: u64,
+}
+
+impl TelemetryStats {
+    pub fn record(&mut self, result: &Result<(), TelemetryError>) {
+        match result {
+            Ok(()) => self.ok += 1,
+            Err(TelemetryError::FieldCount { .. }) => self.field_count_errors += 1,
+            Err(TelemetryError::BadNumber { .. }) => self.number_errors += 1,
+            Err(TelemetryError::OutOfRange { .. }) => self.range_errors += 1,
+            Err(TelemetryError::BadHeader(_)) => self.header_errors += 1,
+        }
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.field_count_errors
+            + self.number_errors
+            + self.range_errors
+            + self.header_errors
+            + self.frame_errors
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} ok, {} wrong field count, {} bad number, {} out of range, {} bad header, \
+             {} bad frames",
+            self.ok,
+            self.field_count_errors,
+            self.number_errors,
+            self.range_errors,
+            self.header_errors,
+            self.frame_errors
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_record() {
+        let schema = Arc::new(Schema::legacy());
+        let record = schema
+            .parse_record("120.5,-118.0,1.5,2.5,1234,100,120,500,300,310")
+            .unwrap();
+        assert_eq!(record.get("motor_1_rpm"), Some(120.5));
+        assert_eq!(record.get("time_ms"), Some(1234.0));
+        assert_eq!(record.get("tof2_mm"), Some(310.0));
+        assert_eq!(record.get("roll"), None);
+
+        assert_eq!(
+            schema.parse_record("120.5,-118.0,1.5"),
+            Err(TelemetryError::FieldCount {
+                expected: 10,
+                found: 3
+            })
+        );
+        assert_eq!(
+            schema.parse_record("120.5,-1\u{fffd}8.0,1.5,2.5,1234,100,120,500,300,310"),
+            Err(TelemetryError::BadNumber {
+                field: "motor_2_rpm".to_string(),
+                value: "-1\u{fffd}8.0".to_string(),
+            })
+        );
+        assert_eq!(
+            schema.parse_record("120.5,-118.0,1.5,2.5,1234,100,120,99999,300,310"),
+            Err(TelemetryError::OutOfRange {
+                field: "sonar_mm".to_string(),
+                value: 99999.0
+            })
+        );
+        assert!(matches!(
+            schema.parse_record("120.5,-118.0,1.5,2.5,1234,100,120,nan,300,310"),
+            Err(TelemetryError::OutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn test_header() {
+        let line = "#motor_1_rpm:rpm, roll:deg, yaw ,sonar_mm:mm\r\n";
+        assert!(Schema::is_header(line));
+        let schema = Arc::new(Schema::parse_header(line).unwrap());
+        assert_eq!(schema.fields.len(), 4);
+        assert_eq!(schema.fields[1].to_string(), "roll [deg]");
+        assert_eq!(schema.fields[2].unit, None);
+
+        let record = schema.parse_record("1500,-3.5,270.25,420").unwrap();
+        assert_eq!(record.get("roll"), Some(-3.5));
+        let names: Vec<&str> = record
+            .iter()
+            .map(|(field, _)| field.name.as_str())
+            .collect();
+        assert_eq!(names, ["motor_1_rpm", "roll", "yaw", "sonar_mm"]);
+
+        assert!(Schema::parse_header("#a,a").is_err());
+        assert!(Schema::parse_header("#a,,b").is_err());
+        assert!(Schema::parse_header("#a b").is_err());
+    }
+}
@@ -0,0 +1,326 @@
+// src/telemetry/binary.rs
+//! Binary framing for the Arduino link, for firmware that needs more than
+//! CSV at 9600 baud can carry. Each frame is
+//!
+//! ```text
+//! message id (1) | sequence (1) | payload | CRC-16 (2, little endian)
+//! ```
+//!
+//! COBS encoded and terminated by a zero byte. The CRC is CRC-16/CCITT-FALSE
+//! over the id, sequence and payload. Each side numbers its frames, so gaps
+//! in the sequence show frames that were lost.
+use std::fmt;
+use std::time::Instant;
+
+use crate::timing::ArrivalTime;
+
+/// Payload is the header text, e.g. `motor_1_rpm:rpm,time_ms:ms`, without
+/// the leading `#`
+pub const MSG_SCHEMA: u8 = 0x01;
+/// Payload is one little endian `f32` per schema field
+pub const MSG_TELEMETRY: u8 = 0x02;
+/// From us: a motor command, see [`crate::motor`]
+pub const MSG_COMMAND: u8 = 0x10;
+/// From the Arduino: the sequence number of the command and a status byte
+pub const MSG_ACK: u8 = 0x11;
+
+/// Longest frame we accept before encoding, anything longer is noise
+const MAX_FRAME_LEN: usize = 512;
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |mut crc: u16, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// Consistent Overhead Byte Stuffing: removes every zero byte, so zero can
+/// mark the end of a frame
+pub fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 254 + 2);
+    let mut code_index = 0;
+    out.push(0);
+    let mut code = 1u8;
+    for &byte in data {
+        if byte != 0 {
+            out.push(byte);
+            code += 1;
+        }
+        if byte == 0 || code == 0xFF {
+            out[code_index] = code;
+            code_index = out.len();
+            out.push(0);
+            code = 1;
+        }
+    }
+    out[code_index] = code;
+    out
+}
+
+/// Undo `cobs_encode`. `None` if the data isn't valid COBS.
+pub fn cobs_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let code = data[i] as usize;
+        if code == 0 || i + code > data.len() {
+            return None;
+        }
+        out.extend_from_slice(&data[i + 1..i + code]);
+        i += code;
+        if code < 0xFF && i < data.len() {
+            out.push(0);
+        }
+    }
+    Some(out)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinaryFrame {
+    pub id: u8,
+    pub seq: u8,
+    pub payload: Vec<u8>,
+}
+
+impl BinaryFrame {
+    /// The frame as sent on the wire, including the trailing zero
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![self.id, self.seq];
+        data.extend_from_slice(&self.payload);
+        let crc = crc16(&data);
+        data.extend_from_slice(&crc.to_le_bytes());
+        let mut encoded = cobs_encode(&data);
+        encoded.push(0);
+        encoded
+    }
+
+    /// Parse a frame that has already had its COBS encoding removed
+    fn parse(data: &[u8]) -> Result<Self, FrameError> {
+        if data.len() < 4 {
+            return Err(FrameError::TooShort);
+        }
+        let (body, crc) = data.split_at(data.len() - 2);
+        let found = u16::from_le_bytes([crc[0], crc[1]]);
+        let calculated = crc16(body);
+        if calculated != found {
+            return Err(FrameError::Crc { calculated, found });
+        }
+        Ok(Self {
+            id: body[0],
+            seq: body[1],
+            payload: body[2..].to_vec(),
+        })
+    }
+}
+
+/// Why bytes between two zeros weren't a valid frame
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameError {
+    Cobs,
+    TooShort,
+    TooLong,
+    Crc { calculated: u16, found: u16 },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Cobs => write!(f, "Invalid COBS encoding"),
+            FrameError::TooShort => write!(f, "Frame too short"),
+            FrameError::TooLong => write!(f, "Frame too long"),
+            FrameError::Crc { calculated, found } => write!(
+                f,
+                "CRC mismatch (calculated {:04X}, found {:04X})",
+                calculated, found
+            ),
+        }
+    }
+}
+
+/// Numbers the frames we send
+#[derive(Debug, Default)]
+pub struct FrameEncoder {
+    seq: u8,
+}
+
+impl FrameEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The sequence number the next frame will have
+    pub fn next_seq(&self) -> u8 {
+        self.seq
+    }
+
+    /// Encode a message with the next sequence number
+    pub fn encode(&mut self, id: u8, payload: &[u8]) -> Vec<u8> {
+        let frame = BinaryFrame {
+            id,
+            seq: self.seq,
+            payload: payload.to_vec(),
+        };
+        self.seq = self.seq.wrapping_add(1);
+        frame.encode()
+    }
+}
+
+/// Splits the byte stream at the zero delimiters and checks each frame
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    /// When the first byte of the frame in `buffer` was read
+    started: Option<Instant>,
+    /// Set when the current frame ran past `MAX_FRAME_LEN`
+    overflow: bool,
+    next_seq: Option<u8>,
+    /// Frames missing from the sequence numbers
+    pub dropped: u64,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add bytes read at `now` and return every frame they complete, with
+    /// when its first and last bytes were read
+    pub fn push(
+        &mut self,
+        data: &[u8],
+        now: Instant,
+    ) -> Vec<(Result<BinaryFrame, FrameError>, ArrivalTime)> {
+        let mut frames = Vec::new();
+        for &byte in data {
+            if byte != 0 {
+                self.started.get_or_insert(now);
+                if self.buffer.len() < MAX_FRAME_LEN {
+                    self.buffer.push(byte);
+                } else {
+                    self.overflow = true;
+                }
+                continue;
+            }
+
+            // Zero ends a frame. Back to back zeros are just idle line.
+            let Some(started) = self.started.take() else {
+                continue;
+            };
+            let arrival = ArrivalTime {
+                first_byte: started,
+                last_byte: now,
+            };
+            let result = if std::mem::take(&mut self.overflow) {
+                Err(FrameError::TooLong)
+            } else {
+                cobs_decode(&self.buffer)
+                    .ok_or(FrameError::Cobs)
+                    .and_then(|decoded| BinaryFrame::parse(&decoded))
+            };
+            self.buffer.clear();
+            if let Ok(frame) = &result {
+                self.track_sequence(frame.seq);
+            }
+            frames.push((result, arrival));
+        }
+        frames
+    }
+
+    fn track_sequence(&mut self, seq: u8) {
+        if let Some(expected) = self.next_seq {
+            self.dropped += seq.wrapping_sub(expected) as u64;
+        }
+        self.next_seq = Some(seq.wrapping_add(1));
+    }
+}
+
+/// Telemetry values as little endian `f32`s
+pub fn encode_values(values: &[f64]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|&value| (value as f32).to_le_bytes())
+        .collect()
+}
+
+/// The values in a telemetry payload. `None` if it isn't a whole number of
+/// `f32`s.
+pub fn decode_values(payload: &[u8]) -> Option<Vec<f64>> {
+    if !payload.len().is_multiple_of(4) {
+        return None;
+    }
+    let values = payload
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64)
+        .collect();
+    Some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc_and_cobs() {
+        // The standard check value for CRC-16/CCITT-FALSE
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+
+        assert_eq!(cobs_encode(&[0x11, 0x00, 0x22]), [0x02, 0x11, 0x02, 0x22]);
+        assert_eq!(cobs_encode(&[0x00]), [0x01, 0x01]);
+        for data in [
+            vec![],
+            vec![0, 0, 0],
+            vec![1, 2, 0, 3],
+            (1..=255).collect::<Vec<u8>>(),
+            (0..600).map(|i| (i % 7) as u8).collect(),
+        ] {
+            let encoded = cobs_encode(&data);
+            assert!(!encoded.contains(&0));
+            assert_eq!(cobs_decode(&encoded), Some(data));
+        }
+    }
+
+    #[test]
+    fn test_decoder() {
+        let now = Instant::now();
+        let mut encoder = FrameEncoder::new();
+        let telemetry = encode_values(&[1500.0, -3.5, 0.0]);
+
+        let mut stream = vec![0, 0];
+        stream.extend(encoder.encode(MSG_SCHEMA, b"rpm:rpm,roll:deg,sonar_mm:mm"));
+        stream.extend(encoder.encode(MSG_TELEMETRY, &telemetry));
+        // A corrupted frame, then one that never arrived
+        let mut corrupt = encoder.encode(MSG_TELEMETRY, &telemetry);
+        corrupt[1] ^= 0x40;
+        stream.extend(corrupt);
+        encoder.encode(MSG_TELEMETRY, &telemetry);
+        stream.extend(encoder.encode(MSG_TELEMETRY, &telemetry));
+
+        let mut decoder = FrameDecoder::new();
+        let mut frames = Vec::new();
+        for chunk in stream.chunks(5) {
+            frames.extend(decoder.push(chunk, now));
+        }
+
+        assert_eq!(frames.len(), 4);
+        let schema = frames[0].0.as_ref().unwrap();
+        assert_eq!(schema.id, MSG_SCHEMA);
+        assert_eq!(schema.payload, b"rpm:rpm,roll:deg,sonar_mm:mm");
+        let values = frames[1].0.as_ref().unwrap();
+        assert_eq!(
+            decode_values(&values.payload),
+            Some(vec![1500.0, -3.5, 0.0])
+        );
+        assert!(matches!(frames[2].0, Err(FrameError::Crc { .. })));
+        assert_eq!(frames[3].0.as_ref().unwrap().seq, 4);
+        // The corrupted frame and the missing one
+        assert_eq!(decoder.dropped, 2);
+    }
+}
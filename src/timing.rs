@@ -0,0 +1,115 @@
+// src/timing.rs
+//! Monotonic arrival times for serial data. The logging thread stamps rows
+//! when it gets to them, which can be well after the bytes came in, so the
+//! readers note when each frame arrived and the logger records that too.
+use std::io::{self, Read};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+static EPOCH: OnceLock<(Instant, SystemTime)> = OnceLock::new();
+
+/// The instant monotonic timestamps are counted from, and the wall clock
+/// time it corresponds to. Fixed the first time it's asked for.
+pub fn epoch() -> (Instant, SystemTime) {
+    *EPOCH.get_or_init(|| (Instant::now(), SystemTime::now()))
+}
+
+/// Nanoseconds from the epoch to `instant`
+pub fn monotonic_nanos(instant: Instant) -> u64 {
+    instant.saturating_duration_since(epoch().0).as_nanos() as u64
+}
+
+/// The epoch as nanoseconds since the UNIX epoch, to turn monotonic
+/// timestamps into wall clock time
+pub fn epoch_unix_nanos() -> u64 {
+    epoch()
+        .1
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+/// When the first and last bytes of a frame were read. Resolution is one
+/// read call, so bytes that came in together share a timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArrivalTime {
+    pub first_byte: Instant,
+    pub last_byte: Instant,
+}
+
+impl ArrivalTime {
+    /// A frame that arrived in a single read
+    pub fn at(instant: Instant) -> Self {
+        Self {
+            first_byte: instant,
+            last_byte: instant,
+        }
+    }
+
+    /// How long the frame took to come in
+    pub fn duration(&self) -> Duration {
+        self.last_byte.saturating_duration_since(self.first_byte)
+    }
+}
+
+/// A value read from a device, along with when it arrived
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timed<T> {
+    pub value: T,
+    pub arrival: ArrivalTime,
+}
+
+/// Wraps a reader to note when data last came out of it, for readers like
+/// `BufReader` that hide the individual reads
+#[derive(Debug)]
+pub struct TimedReader<R> {
+    inner: R,
+    last_read: Instant,
+}
+
+impl<R> TimedReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            last_read: Instant::now(),
+        }
+    }
+
+    /// When the last read that returned data finished
+    pub fn last_read(&self) -> Instant {
+        self.last_read
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+impl<R: Read> Read for TimedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.last_read = Instant::now();
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monotonic_nanos() {
+        let (start, _) = epoch();
+        assert_eq!(monotonic_nanos(start), 0);
+        assert_eq!(monotonic_nanos(start + Duration::from_millis(5)), 5_000_000);
+        assert!(epoch_unix_nanos() > 0);
+
+        let arrival = ArrivalTime {
+            first_byte: start,
+            last_byte: start + Duration::from_millis(3),
+        };
+        assert_eq!(arrival.duration(), Duration::from_millis(3));
+    }
+}
@@ -1,49 +1,73 @@
-use serialport;
 use serialport::Error;
 use serialport::TTYPort;
-use std::io::{self, BufRead, BufReader, Write};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::collections::VecDeque;
 use std::path::PathBuf;
-use std::time::Duration;
-
-
-#[derive(Debug, Clone)]
-pub struct SensorData {
-    pub motor_1_rpm: f32,
-    pub motor_2_rpm: f32,
-    pub motor_1_tot_rotations: f32,
-    pub motor_2_tot_rotations: f32,
-    pub time_ms: u32,
-    // pub roll: f32,
-    // pub pitch: f32,
-    // pub yaw: f32,
-    pub current_motor_1_ma: f32,
-    pub current_motor_2_ma: f32,
-    // pub acc_x: f32,
-    // pub acc_y: f32,
-    // pub acc_z: f32,
-    pub sonar_mm: f32,
-    pub tof1_mm: f32,
-    pub tof2_mm: f32,
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::baud;
+use crate::motor::pid::RpmController;
+use crate::motor::{CommandOutcome, CommandTracker, MotorCommand};
+use crate::telemetry::binary::{self, BinaryFrame, FrameDecoder, FrameError};
+use crate::telemetry::{Schema, TelemetryError, TelemetryRecord};
+use crate::timing::{ArrivalTime, Timed, TimedReader};
+
+
+/// How the Arduino firmware sends its data
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum ArduinoProtocol {
+    /// Comma separated lines
+    #[default]
+    Text,
+    /// COBS framed binary messages with a CRC
+    Binary,
 }
 
 
 #[derive(Debug)]
-pub struct ArduinoSerialPort {
-    reader: BufReader<TTYPort>,  // Buffered reader for line-based reads
+pub struct ArduinoSerialPort<P = TTYPort> {
+    reader: BufReader<TimedReader<P>>,  // Buffered reader for line-based reads
+    baud_rate: u32,
+    protocol: ArduinoProtocol,
+    /// Fields the Arduino announced in its header, or the legacy layout
+    schema: Arc<Schema>,
+    decoder: FrameDecoder,
+    /// Binary frames decoded but not returned yet
+    pending: VecDeque<(Result<BinaryFrame, FrameError>, ArrivalTime)>,
+    /// Motor commands waiting for an answer
+    commands: CommandTracker,
+    /// Commands answered since the last `poll_commands`
+    answered: Vec<CommandOutcome>,
 }
 
 
-pub fn open_port(port: PathBuf) -> Result<ArduinoSerialPort, Error> {
+/// Open the Arduino port at `baud_rate`, or detect the rate if it's `None`
+pub fn open_port(
+    port: PathBuf,
+    baud_rate: Option<u32>,
+    protocol: ArduinoProtocol,
+) -> Result<ArduinoSerialPort, Error> {
     const BAUD_RATE: u32 = 9600;
+    // Opening the port resets the Arduino, which takes a moment to boot
+    const DETECT_LISTEN: Duration = Duration::from_secs(3);
 
-    match serialport::new(port.to_string_lossy(), BAUD_RATE)
+    let looks_valid = match protocol {
+        ArduinoProtocol::Text => baud::arduino_output_valid,
+        ArduinoProtocol::Binary => baud::arduino_binary_output_valid,
+    };
+    let baud_rate = baud_rate.unwrap_or_else(|| {
+        baud::detect_baud_rate(&port, BAUD_RATE, DETECT_LISTEN, looks_valid).unwrap_or(BAUD_RATE)
+    });
+
+    match serialport::new(port.to_string_lossy(), baud_rate)
         .timeout(Duration::from_secs(1))  // 1s timeout for reads
         .open_native()
     {
         Ok(arduino_port) => {
-            println!("Successfully opened port {} at {} baud.", port.to_string_lossy(), BAUD_RATE);
-            let reader = BufReader::new(arduino_port);  // Wrap for buffered line reads
-            Ok(ArduinoSerialPort { reader })
+            println!("Successfully opened port {} at {} baud.", port.to_string_lossy(), baud_rate);
+            Ok(ArduinoSerialPort::new(arduino_port, baud_rate, protocol))
         }
         Err(e) => {
             eprintln!("Failed to open \"{}\". Error: {}", port.to_string_lossy(), e);
@@ -53,83 +77,212 @@ pub fn open_port(port: PathBuf) -> Result<ArduinoSerialPort, Error> {
 }
 
 
-impl ArduinoSerialPort {
-    pub fn read_line(&mut self) -> Result<Option<SensorData>, Error> {
-        let mut line = String::new();
-        match self.reader.read_line(&mut line) {
+/// Reading from the Arduino failed, either on the port or in the data
+#[derive(Debug)]
+pub enum ArduinoError {
+    Io(Error),
+    /// The line that came in, and what was wrong with it
+    Telemetry { line: String, error: TelemetryError },
+    /// A binary frame that was damaged on the way
+    Frame(FrameError),
+    /// A binary frame with a message id we don't know, e.g. from newer
+    /// firmware
+    UnknownMessage(u8),
+}
+
+impl fmt::Display for ArduinoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArduinoError::Io(e) => write!(f, "{}", e),
+            ArduinoError::Telemetry { line, error } => write!(f, "{}: {}", error, line),
+            ArduinoError::Frame(e) => write!(f, "{}", e),
+            ArduinoError::UnknownMessage(id) => write!(f, "Unknown message id 0x{:02X}", id),
+        }
+    }
+}
+
+impl From<io::Error> for ArduinoError {
+    fn from(e: io::Error) -> Self {
+        ArduinoError::Io(Error::from(e))
+    }
+}
+
+impl<P: Read + Write> ArduinoSerialPort<P> {
+    pub fn new(port: P, baud_rate: u32, protocol: ArduinoProtocol) -> Self {
+        let reader = BufReader::new(TimedReader::new(port));  // Wrap for buffered line reads
+        ArduinoSerialPort {
+            reader,
+            baud_rate,
+            protocol,
+            schema: Arc::new(Schema::legacy()),
+            decoder: FrameDecoder::new(),
+            pending: VecDeque::new(),
+            commands: CommandTracker::new(),
+            answered: Vec::new(),
+        }
+    }
+
+    /// Read one record of sensor data, with when its first and last bytes
+    /// were read. A line or frame that can't be used comes back as an error,
+    /// and the next call carries on with the following one. Headers update
+    /// the schema and return `None`.
+    pub fn read_record(&mut self) -> Result<Option<Timed<TelemetryRecord>>, ArduinoError> {
+        match self.protocol {
+            ArduinoProtocol::Text => self.read_line(),
+            ArduinoProtocol::Binary => self.read_frame(),
+        }
+    }
+
+    fn read_line(&mut self) -> Result<Option<Timed<TelemetryRecord>>, ArduinoError> {
+        // If the line didn't start in bytes we've already buffered, it starts
+        // with the next read
+        if self.reader.buffer().is_empty() {
+            match self.reader.fill_buf() {
+                Ok(_) => {}
+                // Nothing was sent within the port timeout
+                Err(e) if e.kind() == ErrorKind::TimedOut => return Ok(None),
+                Err(e) => return Err(ArduinoError::from(e)),
+            }
+        }
+        let first_byte = self.reader.get_ref().last_read();
+
+        // Read raw bytes, so a garbled byte shows up in the line rather than
+        // as an I/O error
+        let mut line = Vec::new();
+        match self.reader.read_until(b'\n', &mut line) {
             Ok(0) => Ok(None),  // EOF (unlikely on serial)
-            Ok(bytes_read) if bytes_read > 0 => {
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&line);
                 // Remove trailing newline chars
-                let trimmed = line.trim_end_matches(|c| c == '\r' || c == '\n');
+                let trimmed = line.trim_end_matches(['\r', '\n']);
                 if trimmed.is_empty() {
                     return Ok(None);
                 }
-                // println!("Read line: {} ({} bytes)", trimmed, bytes_read);
-
-                // Parse the line into SensorData
-                let parts: Vec<&str> = trimmed.split(",").map(|s| s.trim()).collect();
-                if parts.len() != 10 {
-                    println!("Invalid Arduino data line: {}", trimmed);
-                    return Err(Error::new(
-                        serialport::ErrorKind::Io(std::io::ErrorKind::InvalidData),
-                        format!("Expected 10 parts, got {}", parts.len()),
-                    ));
-                }
+                // println!("Read line: {}", trimmed);
 
-                let motor_1_rpm: f32 = parts[0].parse().unwrap();
-                let motor_2_rpm: f32 = parts[1].parse().unwrap();
-                let motor_1_tot_rotations: f32 = parts[2].parse().unwrap();
-                let motor_2_tot_rotations: f32 = parts[3].parse().unwrap();
-                let time_ms: u32 = parts[4].parse().unwrap();
-                // let roll: f32 = parts[5].parse().unwrap();
-                // let pitch: f32 = parts[6].parse().unwrap();
-                // let yaw: f32 = parts[7].parse().unwrap();
-                let current_motor_1_ma: f32 = parts[5].parse().unwrap();
-                let current_motor_2_ma: f32 = parts[6].parse().unwrap();
-                let sonar_mm: f32 = parts[7].parse().unwrap();
-                let tof1_mm: f32 = parts[8].parse().unwrap();
-                let tof2_mm: f32 = parts[9].parse().unwrap();
-                // let acc_x: f32 = parts[7].parse().unwrap();
-                // let acc_y: f32 = parts[8].parse().unwrap();
-                // let acc_z: f32 = parts[9].parse().unwrap();
-
-                let data = SensorData {
-                    motor_1_rpm,
-                    motor_2_rpm,
-                    motor_1_tot_rotations,
-                    motor_2_tot_rotations,
-                    time_ms,
-                    sonar_mm,
-                    tof1_mm,
-                    tof2_mm,
-                    // roll,
-                    // pitch,
-                    // yaw,
-                    current_motor_1_ma,
-                    current_motor_2_ma,
-                    // acc_x,
-                    // acc_y,
-                    // acc_z,
+                let telemetry_error = |error| ArduinoError::Telemetry {
+                    line: trimmed.to_string(),
+                    error,
                 };
+                if Schema::is_header(trimmed) {
+                    let schema = Schema::parse_header(trimmed).map_err(telemetry_error)?;
+                    println!("Arduino fields: {}", schema);
+                    self.schema = Arc::new(schema);
+                    return Ok(None);
+                }
+                let data = self.schema.parse_record(trimmed).map_err(telemetry_error)?;
 
                 // println!("Parsed data: {:?}", data);
-                Ok(Some(data))
+                let arrival = ArrivalTime {
+                    first_byte,
+                    last_byte: self.reader.get_ref().last_read(),
+                };
+                Ok(Some(Timed { value: data, arrival }))
             }
-            Ok(_) => Ok(None),  // Should not happen
-            Err(e) => {
-                eprintln!("Error reading line from Arduino serial port: {}", e);
-                Err(Error::from(e))
+            Err(e) if e.kind() == ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(ArduinoError::from(e)),
+        }
+    }
+
+    fn read_frame(&mut self) -> Result<Option<Timed<TelemetryRecord>>, ArduinoError> {
+        if self.pending.is_empty() {
+            let data = match self.reader.fill_buf() {
+                Ok(data) => data.to_vec(),
+                Err(e) if e.kind() == ErrorKind::TimedOut => return Ok(None),
+                Err(e) => return Err(ArduinoError::from(e)),
+            };
+            self.reader.consume(data.len());
+            let now = self.reader.get_ref().last_read();
+            self.pending.extend(self.decoder.push(&data, now));
+        }
+        let Some((frame, arrival)) = self.pending.pop_front() else {
+            return Ok(None);
+        };
+        let frame = frame.map_err(ArduinoError::Frame)?;
+
+        let telemetry_error = |error| ArduinoError::Telemetry {
+            line: format!("{:02x?}", frame.payload),
+            error,
+        };
+        match frame.id {
+            binary::MSG_SCHEMA => {
+                let header = format!("#{}", String::from_utf8_lossy(&frame.payload));
+                let schema = Schema::parse_header(&header).map_err(telemetry_error)?;
+                println!("Arduino fields: {}", schema);
+                self.schema = Arc::new(schema);
+                Ok(None)
+            }
+            binary::MSG_TELEMETRY => {
+                let values = binary::decode_values(&frame.payload).ok_or_else(|| {
+                    telemetry_error(TelemetryError::FieldCount {
+                        expected: self.schema.fields.len(),
+                        found: frame.payload.len() / 4,
+                    })
+                })?;
+                let data = self.schema.record(values).map_err(telemetry_error)?;
+                Ok(Some(Timed { value: data, arrival }))
+            }
+            // An answer to a command we've given up on, or already had an
+            // answer to, is dropped
+            binary::MSG_ACK => {
+                if let Some(outcome) = self.commands.acknowledge(&frame, arrival.last_byte) {
+                    self.answered.push(outcome);
+                }
+                Ok(None)
             }
+            id => Err(ArduinoError::UnknownMessage(id)),
         }
     }
 
-    pub fn get_port_mut(&mut self) -> &mut TTYPort {
-        self.reader.get_mut()
+    /// Send a motor command. Its answer turns up in `poll_commands`. Only
+    /// the binary protocol can carry commands.
+    pub fn send_command(&mut self, command: MotorCommand) -> io::Result<()> {
+        if self.protocol != ArduinoProtocol::Binary {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Motor commands need the binary protocol",
+            ));
+        }
+        let port = self.reader.get_mut().get_mut();
+        self.commands.send(port, command, Instant::now())
+    }
+
+    /// Send again the commands that haven't been answered in time, and
+    /// return the ones that were answered or have timed out since the last
+    /// call
+    pub fn poll_commands(&mut self) -> io::Result<Vec<CommandOutcome>> {
+        let port = self.reader.get_mut().get_mut();
+        let timed_out = self.commands.poll(port, Instant::now())?;
+        let mut outcomes = std::mem::take(&mut self.answered);
+        outcomes.extend(timed_out);
+        Ok(outcomes)
+    }
+
+    /// Binary frames lost on the way, going by their sequence numbers
+    pub fn dropped_frames(&self) -> u64 {
+        self.decoder.dropped
+    }
+
+    pub fn protocol(&self) -> ArduinoProtocol {
+        self.protocol
+    }
+
+    /// The fields the Arduino is sending
+    pub fn schema(&self) -> &Arc<Schema> {
+        &self.schema
+    }
+
+    pub fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+
+    pub fn get_port_mut(&mut self) -> &mut P {
+        self.reader.get_mut().get_mut()
     }
 }
 
 
-impl Write for ArduinoSerialPort {
+impl<P: Read + Write> Write for ArduinoSerialPort<P> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.get_port_mut().write(buf)
     }
@@ -139,3 +292,89 @@ impl Write for ArduinoSerialPort {
     }
 }
 
+
+
+/// Stop the motors if `controller` has gone too long without an RPM reading,
+/// whether the Arduino went quiet, sent nothing but garbage or was unplugged.
+/// Returns `None` while the readings keep coming, otherwise how sending the
+/// stop went.
+pub fn stop_if_readings_lost<P: Read + Write>(
+    controller: &mut RpmController,
+    port: Option<&mut ArduinoSerialPort<P>>,
+    now: Instant,
+) -> Option<io::Result<()>> {
+    if !controller.readings_lost(now) {
+        return None;
+    }
+    Some(port.map_or(Ok(()), |port| port.send_command(MotorCommand::Stop)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::motor::pid::MotorControlConfig;
+    use crate::telemetry::binary::FrameEncoder;
+    use std::io::Cursor;
+
+    /// Reads `input`, and keeps what's written for the test to check
+    #[derive(Debug)]
+    struct MockPort {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockPort {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockPort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_garbled_telemetry_stops_motors() {
+        let mut encoder = FrameEncoder::new();
+        let values = [900.0, 900.0, 1.5, 2.5, 1234.0, 100.0, 120.0, 500.0, 300.0, 310.0];
+        let mut input = encoder.encode(binary::MSG_TELEMETRY, &binary::encode_values(&values));
+        // After one good record, only a damaged frame, one short of values
+        // and one we don't know
+        input.extend([0x05, 0x01, 0x02, 0x00]);
+        input.extend(encoder.encode(binary::MSG_TELEMETRY, &binary::encode_values(&values[..2])));
+        input.extend(encoder.encode(0x7F, &[]));
+        let port = MockPort {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        };
+        let mut port = ArduinoSerialPort::new(port, 115200, ArduinoProtocol::Binary);
+        let mut controller = RpmController::new(&MotorControlConfig {
+            motor_1_rpm: Some(1000.0),
+            ..MotorControlConfig::default()
+        });
+
+        let record = port.read_record().unwrap().unwrap();
+        let read_at = record.arrival.last_byte;
+        assert_eq!(controller.update(&record.value, read_at).len(), 1);
+        assert!(matches!(port.read_record(), Err(ArduinoError::Frame(_))));
+        assert!(matches!(port.read_record(), Err(ArduinoError::Telemetry { .. })));
+        assert!(matches!(port.read_record(), Err(ArduinoError::UnknownMessage(0x7F))));
+        assert!(matches!(port.read_record(), Ok(None)));
+
+        let later = read_at + Duration::from_secs(2);
+        let stopped = stop_if_readings_lost(&mut controller, Some(&mut port), later);
+        assert!(stopped.is_some_and(|result| result.is_ok()));
+        let sent = FrameDecoder::new().push(&port.get_port_mut().output, later);
+        assert_eq!(sent.len(), 1);
+        let stop = sent[0].0.as_ref().unwrap();
+        assert_eq!(stop.id, binary::MSG_COMMAND);
+        assert_eq!(stop.payload, MotorCommand::Stop.payload());
+    }
+}